@@ -1,8 +1,6 @@
 #!/bin/bash
 #Build
 sudo apt-get install libsdl2-dev libsdl2-image-dev libsdl2-ttf-dev libssl-dev
-#Runtime
-sudo apt-get install p7zip
 #Emulators
 ##DS
 sudo apt-get install desmume
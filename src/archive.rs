@@ -0,0 +1,291 @@
+extern crate sevenz_rust;
+extern crate zip;
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use library;
+use ls;
+use rom::Progress;
+use scan;
+use Playing;
+
+/// Extensions of archives that can be extracted in-process
+pub const EXTENSIONS: &'static [&'static str] = &["zip", "7z"];
+
+pub fn is_archive(path: &str) -> bool {
+    let ext = scan::extension(path);
+    EXTENSIONS.iter().any(|x| *x == ext)
+}
+
+fn extract_zip(path: &str, dest: &Path, progress: &Arc<Mutex<Progress>>) -> io::Result<()> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+
+    let total = archive.len() as u64;
+    for i in 0 .. archive.len() {
+        let mut file = archive.by_index(i)?;
+        // Entries that would escape the destination are skipped
+        let out_path = match file.enclosed_name() {
+            Some(name) => dest.join(name),
+            None => continue
+        };
+        if file.is_dir() {
+            fs::create_dir_all(&out_path)?;
+        } else {
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            io::copy(&mut file, &mut File::create(&out_path)?)?;
+        }
+
+        if let Ok(mut progress) = progress.lock() {
+            *progress = Progress::InProgress(i as u64 + 1, total);
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_7z(path: &str, dest: &Path) -> io::Result<()> {
+    sevenz_rust::decompress_file(path, dest).map_err(|err| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{}", err))
+    })
+}
+
+/// Extract a zip or 7z archive into a directory
+pub fn extract(path: &str, dest: &Path, progress: &Arc<Mutex<Progress>>) -> io::Result<()> {
+    fs::create_dir_all(dest)?;
+
+    match scan::extension(path).as_str() {
+        "zip" => extract_zip(path, dest, progress),
+        "7z" => extract_7z(path, dest),
+        ext => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unsupported archive: {}", ext)))
+    }
+}
+
+fn dir_size(path: &Path) -> u64 {
+    let mut size = 0;
+    if let Ok(read_dir) = fs::read_dir(path) {
+        for entry_result in read_dir {
+            if let Ok(entry) = entry_result {
+                if let Ok(metadata) = entry.metadata() {
+                    if metadata.is_dir() {
+                        size += dir_size(&entry.path());
+                    } else {
+                        size += metadata.len();
+                    }
+                }
+            }
+        }
+    }
+    size
+}
+
+/// Remove the least recently used games from the cache until it fits in `max_size` bytes.
+///
+/// Games holding a path in `keep`, such as the one just extracted or the one being played, are never removed.
+pub fn evict(max_size: u64, keep: &[PathBuf]) {
+    let root = library::cache_dir();
+
+    let mut games = Vec::new();
+    for system in ls(&root.to_string_lossy()) {
+        for game in ls(&system) {
+            let used = library::mtime(&format!("{}/.used", game));
+            games.push((used, dir_size(Path::new(&game)), game));
+        }
+    }
+    games.sort();
+
+    let mut size: u64 = games.iter().map(|game| game.1).sum();
+    for (_, game_size, game) in games {
+        if size <= max_size {
+            break;
+        }
+        if keep.iter().any(|path| path.starts_with(&game)) {
+            continue;
+        }
+
+        println!("evicting {} from cache", game);
+        match fs::remove_dir_all(&game) {
+            Ok(()) => size -= game_size,
+            Err(err) => println!("{}: {}", game, err)
+        }
+    }
+}
+
+/// Marks a finished extraction, so a half-written one is never launched
+const COMPLETE: &'static str = ".complete";
+
+/// Extracts an archive on a worker thread, finding the ROM inside when done
+pub struct Extract {
+    progress: Arc<Mutex<Progress>>,
+    result: JoinHandle<Option<String>>,
+}
+
+impl Extract {
+    /// Extract `path` into `dest`, then evict the cache down to `cache_size` bytes if one is given,
+    /// keeping the game being played
+    pub fn new(path: &str, dest: &Path, extensions: &[String], cache_size: Option<u64>, playing: Option<Playing>) -> Extract {
+        let progress = Arc::new(Mutex::new(Progress::Connecting));
+        let progress_child = progress.clone();
+        let path_child = path.to_string();
+        let dest_child = dest.to_path_buf();
+        let extensions_child = extensions.to_vec();
+
+        let result = thread::spawn(move || -> Option<String> {
+            let set_progress = |value: Progress| {
+                if let Ok(mut progress) = progress_child.lock() {
+                    *progress = value;
+                }
+            };
+
+            // Finished extractions into the cache are reused, and marked as recently used
+            let dest_str = dest_child.to_string_lossy().to_string();
+            let complete = dest_child.join(COMPLETE);
+            if cache_size.is_some() && complete.is_file() {
+                if let Some(file) = scan::find_rom(&dest_str, &extensions_child) {
+                    let _ = File::create(dest_child.join(".used"));
+                    set_progress(Progress::Complete);
+                    return Some(file);
+                }
+            }
+
+            // Whatever an interrupted extraction left behind is started over
+            if cache_size.is_some() && dest_child.is_dir() {
+                if let Err(err) = fs::remove_dir_all(&dest_child) {
+                    println!("{}: {}", dest_str, err);
+                }
+            }
+
+            if let Err(err) = extract(&path_child, &dest_child, &progress_child) {
+                println!("extract {}: {}", path_child, err);
+                set_progress(Progress::Error(format!("{}", err)));
+                return None;
+            }
+
+            let file = scan::find_rom(&dest_str, &extensions_child);
+            if file.is_none() {
+                set_progress(Progress::Error("No ROM in archive".to_string()));
+                return None;
+            }
+
+            if let Some(max_size) = cache_size {
+                let size = dir_size(&dest_child);
+                if size > max_size {
+                    let _ = fs::remove_dir_all(&dest_child);
+                    set_progress(Progress::Error(format!(
+                        "{} MB extracted does not fit in the launch cache of {} MB, see cache_size",
+                        size / 1024 / 1024,
+                        max_size / 1024 / 1024
+                    )));
+                    return None;
+                }
+
+                let _ = File::create(dest_child.join(".used"));
+                if let Err(err) = File::create(&complete) {
+                    println!("{}: {}", complete.display(), err);
+                }
+
+                let mut keep = vec![dest_child.clone()];
+                if let Some(ref playing) = playing {
+                    if let Ok(playing) = playing.lock() {
+                        if let Some((_, ref file)) = *playing {
+                            keep.push(PathBuf::from(file));
+                        }
+                    }
+                }
+                evict(max_size, &keep);
+            }
+
+            set_progress(Progress::Complete);
+            file
+        });
+
+        Extract {
+            progress: progress,
+            result: result,
+        }
+    }
+
+    /// Extract into the launch cache, for systems that keep their ROMs compressed
+    pub fn cached(system: &str, name: &str, path: &str, extensions: &[String], cache_size: u64, playing: &Playing) -> Extract {
+        let mut dest = library::cache_dir();
+        dest.push(system);
+        dest.push(name);
+        Extract::new(path, &dest, extensions, Some(cache_size), Some(playing.clone()))
+    }
+
+    /// Extract a downloaded archive next to itself.
+    ///
+    /// The contents go through a folder of their own first, so the ROM found is this archive's
+    /// even when other games sit beside it, as in a flat layout.
+    pub fn beside(path: &str, extensions: &[String]) -> Extract {
+        let progress = Arc::new(Mutex::new(Progress::Connecting));
+        let progress_child = progress.clone();
+        let path_child = path.to_string();
+        let parent = match Path::new(path).parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => PathBuf::from(".")
+        };
+        // Hidden, so the scanner never takes it for a game
+        let temp = parent.join(format!(".{}.extract", scan::file_name(path)));
+        let extensions_child = extensions.to_vec();
+
+        let result = thread::spawn(move || -> Option<String> {
+            let set_progress = |value: Progress| {
+                if let Ok(mut progress) = progress_child.lock() {
+                    *progress = value;
+                }
+            };
+
+            let _ = fs::remove_dir_all(&temp);
+            if let Err(err) = extract(&path_child, &temp, &progress_child) {
+                println!("extract {}: {}", path_child, err);
+                set_progress(Progress::Error(format!("{}", err)));
+                return None;
+            }
+
+            let found = scan::find_rom(&temp.to_string_lossy(), &extensions_child);
+            for entry in ls(&temp.to_string_lossy()) {
+                let to = parent.join(scan::file_name(&entry));
+                if let Err(err) = fs::rename(&entry, &to) {
+                    println!("{}: {}", to.display(), err);
+                }
+            }
+            let _ = fs::remove_dir_all(&temp);
+
+            match found {
+                Some(file) => {
+                    set_progress(Progress::Complete);
+                    Some(parent.join(scan::file_name(&file)).to_string_lossy().into_owned())
+                },
+                None => {
+                    set_progress(Progress::Error("No ROM in archive".to_string()));
+                    None
+                }
+            }
+        });
+
+        Extract {
+            progress: progress,
+            result: result,
+        }
+    }
+
+    pub fn progress(&self) -> Progress {
+        match self.progress.lock() {
+            Ok(progress) => progress.clone(),
+            Err(err) => Progress::Error(format!("{}", err))
+        }
+    }
+
+    pub fn result(self) -> Option<String> {
+        match self.result.join() {
+            Ok(file) => file,
+            Err(_) => None
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use dat::Dats;
+use emulator::EmulatorConfig;
+use library::{self, Index};
+use rom::Progress;
+use settings::Settings;
+
+/// The DATs of a system, or an error if there are none to audit against
+fn load_dats(dir: &str, names: &[String]) -> Result<Dats, String> {
+    let dats = Dats::load(dir, names);
+    if dats.is_empty() {
+        Err(format!("No DAT files in {}", dir))
+    } else {
+        Ok(dats)
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') {
+        format!("\"{}\"", field.replace("\"", "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Comparison of a system's library against its DAT files
+#[derive(Clone, Debug, Default)]
+pub struct Audit {
+    pub system: String,
+    /// Games in the DAT with at least one matching file
+    pub have: Vec<String>,
+    /// Games in the DAT with no matching file
+    pub missing: Vec<String>,
+    /// Games in the library that match nothing in the DAT
+    pub unmatched: Vec<String>,
+    /// Games in the library whose folder name differs from the DAT, as (folder, DAT name)
+    pub misnamed: Vec<(String, String)>,
+}
+
+impl Audit {
+    pub fn new(system: &str, index: &Index, dats: &Dats) -> Audit {
+        let mut have = BTreeSet::new();
+        let mut unmatched = Vec::new();
+        let mut misnamed = Vec::new();
+
+        for entry in index.roms.values() {
+            match dats.find(&entry.hashes) {
+                Some(rom) => {
+                    have.insert(rom.game.clone());
+                    if entry.name != rom.game {
+                        misnamed.push((entry.name.clone(), rom.game.clone()));
+                    }
+                },
+                None => unmatched.push(entry.name.clone())
+            }
+        }
+
+        let mut missing = BTreeSet::new();
+        for dat in dats.dats.iter() {
+            for rom in dat.roms.iter() {
+                if ! have.contains(&rom.game) {
+                    missing.insert(rom.game.clone());
+                }
+            }
+        }
+
+        Audit {
+            system: system.to_string(),
+            have: have.into_iter().collect(),
+            missing: missing.into_iter().collect(),
+            unmatched: unmatched,
+            misnamed: misnamed,
+        }
+    }
+
+    /// Audit a system against an up to date copy of its index.
+    ///
+    /// The index is brought up to date in memory only, as the frontend may be running and owns the saved one.
+    pub fn run(system: &str, config: &EmulatorConfig, settings: &Settings) -> Result<Audit, String> {
+        let dats = load_dats(settings.dats(), config.dats())?;
+        let dats_mtime = library::mtime(settings.dats());
+
+        let mut index = Index::load(system);
+        index.reconcile(&config.roots(), &config.layout(), &config.extensions(), &dats, dats_mtime, |_, _, _| ());
+
+        Ok(Audit::new(system, &index, &dats))
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{}: {} of {} games, {} missing, {} unmatched, {} misnamed",
+            self.system,
+            self.have.len(),
+            self.have.len() + self.missing.len(),
+            self.missing.len(),
+            self.unmatched.len(),
+            self.misnamed.len()
+        )
+    }
+
+    /// The report as lines of text, one section per status
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![self.summary()];
+
+        lines.push(format!("Have: {}", self.have.len()));
+        for name in self.have.iter() {
+            lines.push(format!("    {}", name));
+        }
+
+        lines.push(format!("Missing: {}", self.missing.len()));
+        for name in self.missing.iter() {
+            lines.push(format!("    {}", name));
+        }
+
+        lines.push(format!("Unmatched: {}", self.unmatched.len()));
+        for name in self.unmatched.iter() {
+            lines.push(format!("    {}", name));
+        }
+
+        lines.push(format!("Misnamed: {}", self.misnamed.len()));
+        for &(ref name, ref canonical) in self.misnamed.iter() {
+            lines.push(format!("    {} -> {}", name, canonical));
+        }
+
+        lines
+    }
+
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for line in self.lines() {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        text
+    }
+
+    /// The report as CSV rows of system, status, name and DAT name, without a header
+    pub fn csv(&self) -> String {
+        let mut rows = Vec::new();
+        for name in self.have.iter() {
+            rows.push(["have", name.as_str(), name.as_str()]);
+        }
+        for name in self.missing.iter() {
+            rows.push(["missing", "", name.as_str()]);
+        }
+        for name in self.unmatched.iter() {
+            rows.push(["unmatched", name.as_str(), ""]);
+        }
+        for &(ref name, ref canonical) in self.misnamed.iter() {
+            rows.push(["misnamed", name.as_str(), canonical.as_str()]);
+        }
+
+        let mut csv = String::new();
+        for row in rows.iter() {
+            csv.push_str(&csv_field(&self.system));
+            for field in row.iter() {
+                csv.push(',');
+                csv.push_str(&csv_field(field));
+            }
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+/// Marks a job failed when its thread ends without finishing, such as by panicking
+struct Unfinished(Arc<Mutex<Progress>>);
+
+impl Drop for Unfinished {
+    fn drop(&mut self) {
+        if let Ok(mut progress) = self.0.lock() {
+            match *progress {
+                Progress::Complete | Progress::Error(_) => (),
+                _ => *progress = Progress::Error("Audit failed".to_string())
+            }
+        }
+    }
+}
+
+/// Audits a system on a worker thread, using the index kept up to date by `library::Scan`
+pub struct Job {
+    progress: Arc<Mutex<Progress>>,
+    result: JoinHandle<Option<Audit>>,
+}
+
+impl Job {
+    pub fn new(system: &str, config: &EmulatorConfig, settings: &Settings) -> Job {
+        let progress = Arc::new(Mutex::new(Progress::Connecting));
+        let progress_child = progress.clone();
+        let system_child = system.to_string();
+        let dats_child = settings.dats().to_string();
+        let dat_names_child = config.dats().to_vec();
+
+        let result = thread::spawn(move || -> Option<Audit> {
+            let _unfinished = Unfinished(progress_child.clone());
+
+            let audit = load_dats(&dats_child, &dat_names_child).map(|dats| {
+                let index = Index::load(&system_child);
+                Audit::new(&system_child, &index, &dats)
+            });
+
+            if let Ok(mut progress) = progress_child.lock() {
+                *progress = match audit {
+                    Ok(_) => Progress::Complete,
+                    Err(ref err) => Progress::Error(err.clone())
+                };
+            }
+
+            audit.ok()
+        });
+
+        Job {
+            progress: progress,
+            result: result,
+        }
+    }
+
+    pub fn progress(&self) -> Progress {
+        match self.progress.lock() {
+            Ok(progress) => progress.clone(),
+            Err(err) => Progress::Error(format!("{}", err))
+        }
+    }
+
+    /// The audit, or why there is none
+    pub fn result(self) -> Result<Audit, String> {
+        let error = match self.progress() {
+            Progress::Error(err) => err,
+            _ => "Audit failed".to_string()
+        };
+        match self.result.join() {
+            Ok(Some(audit)) => Ok(audit),
+            _ => Err(error)
+        }
+    }
+}
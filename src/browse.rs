@@ -0,0 +1,194 @@
+use std::cmp::Ordering;
+use std::path::PathBuf;
+
+use history::History;
+use library;
+use rom::{Rom, RomFlags};
+
+/// Order of the ROM grid
+#[derive(Clone, Debug, PartialEq, RustcDecodable, RustcEncodable)]
+pub enum Sort {
+    Name,
+    LastPlayed,
+    PlayCount,
+    Added,
+    Size,
+}
+
+impl Sort {
+    fn next(&self) -> Sort {
+        match *self {
+            Sort::Name => Sort::LastPlayed,
+            Sort::LastPlayed => Sort::PlayCount,
+            Sort::PlayCount => Sort::Added,
+            Sort::Added => Sort::Size,
+            Sort::Size => Sort::Name,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match *self {
+            Sort::Name => "Name",
+            Sort::LastPlayed => "Last played",
+            Sort::PlayCount => "Play count",
+            Sort::Added => "Date added",
+            Sort::Size => "Size",
+        }
+    }
+}
+
+/// Which ROMs the grid shows
+#[derive(Clone, Debug, PartialEq, RustcDecodable, RustcEncodable)]
+pub enum Filter {
+    All,
+    Favorites,
+    NeverPlayed,
+    Flag(RomFlags),
+    Region(String),
+}
+
+impl Filter {
+    pub fn name(&self) -> String {
+        match *self {
+            Filter::All => "All".to_string(),
+            Filter::Favorites => "Favorites".to_string(),
+            Filter::NeverPlayed => "Never played".to_string(),
+            Filter::Flag(ref flag) => format!("{:?}", flag),
+            Filter::Region(ref region) => region.clone(),
+        }
+    }
+
+    fn matches(&self, system: &str, rom: &Rom, history: &History) -> bool {
+        match *self {
+            Filter::All => true,
+            Filter::Favorites => history.favorite(system, &rom.config.name),
+            Filter::NeverPlayed => history.get(system, &rom.config.name).is_none(),
+            Filter::Flag(ref flag) => rom.config.flags.contains(flag),
+            Filter::Region(ref region) => rom.config.tags.regions.contains(region),
+        }
+    }
+}
+
+/// Title for sorting by name, without a leading article.
+///
+/// Handles both "The Legend of Zelda" and No-Intro style "Legend of Zelda, The".
+pub fn sort_name(title: &str) -> String {
+    let mut name = title.to_lowercase();
+    for article in ["the ", "a ", "an "].iter() {
+        if name.starts_with(article) {
+            name = name[article.len() ..].to_string();
+            break;
+        }
+    }
+    for article in [", the", ", a", ", an"].iter() {
+        if let Some(i) = name.find(article) {
+            let rest = &name[i + article.len() ..];
+            if rest.is_empty() || rest.starts_with(' ') {
+                name = format!("{}{}", &name[.. i], rest);
+                break;
+            }
+        }
+    }
+    name
+}
+
+/// Sort and filter of a system's ROM grid, remembered per system
+#[derive(Clone, Debug, RustcDecodable, RustcEncodable)]
+pub struct Browse {
+    pub sort: Sort,
+    pub filter: Filter,
+    /// Show one tile per game, and one file per game in the download list, which is the default
+    pub group: Option<bool>,
+}
+
+impl Default for Browse {
+    fn default() -> Browse {
+        Browse {
+            sort: Sort::Name,
+            filter: Filter::All,
+            group: None,
+        }
+    }
+}
+
+impl Browse {
+    pub fn path(system: &str) -> PathBuf {
+        let mut path = library::data_dir();
+        path.push("browse");
+        path.push(format!("{}.json", system));
+        path
+    }
+
+    pub fn load(system: &str) -> Browse {
+        library::load_json(&Browse::path(system))
+    }
+
+    pub fn save(&self, system: &str) {
+        library::save_json(&Browse::path(system), self);
+    }
+
+    pub fn next_sort(&mut self, system: &str) {
+        self.sort = self.sort.next();
+        self.save(system);
+    }
+
+    pub fn grouped(&self) -> bool {
+        self.group.unwrap_or(true)
+    }
+
+    pub fn toggle_group(&mut self, system: &str) {
+        self.group = Some(! self.grouped());
+        self.save(system);
+    }
+
+    /// Move to the next filter, offering only flags and regions that some ROM has
+    pub fn next_filter(&mut self, system: &str, roms: &[Rom]) {
+        let mut filters = vec![Filter::All, Filter::Favorites, Filter::NeverPlayed];
+        for rom in roms.iter() {
+            for flag in rom.config.flags.iter() {
+                let filter = Filter::Flag(flag.clone());
+                if ! filters.contains(&filter) {
+                    filters.push(filter);
+                }
+            }
+        }
+        let mut regions = Vec::new();
+        for rom in roms.iter() {
+            for region in rom.config.tags.regions.iter() {
+                if ! regions.contains(region) {
+                    regions.push(region.clone());
+                }
+            }
+        }
+        regions.sort();
+        filters.extend(regions.into_iter().map(Filter::Region));
+
+        let i = filters.iter().position(|x| *x == self.filter).map(|i| i + 1).unwrap_or(0);
+        self.filter = filters.get(i).cloned().unwrap_or(Filter::All);
+        self.save(system);
+    }
+
+    /// Indexes of the ROMs to show, in order
+    pub fn order(&self, system: &str, roms: &[Rom], history: &History) -> Vec<usize> {
+        let mut order: Vec<usize> = (0 .. roms.len())
+            .filter(|&i| self.filter.matches(system, &roms[i], history))
+            .collect();
+
+        let plays = |i: usize| history.get(system, &roms[i].config.name);
+        let name = |i: usize| sort_name(roms[i].config.title());
+
+        // Everything but name puts the largest or most recent first, then goes by name
+        order.sort_by(|&a, &b| {
+            let ordering = match self.sort {
+                Sort::Name => Ordering::Equal,
+                Sort::LastPlayed => plays(b).map(|x| x.last).cmp(&plays(a).map(|x| x.last)),
+                Sort::PlayCount => plays(b).map(|x| x.count).cmp(&plays(a).map(|x| x.count)),
+                Sort::Added => roms[b].config.added.cmp(&roms[a].config.added),
+                Sort::Size => roms[b].config.size.cmp(&roms[a].config.size),
+            };
+            ordering.then_with(|| name(a).cmp(&name(b)))
+        });
+
+        order
+    }
+}
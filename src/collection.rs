@@ -0,0 +1,270 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use sdl2::pixels::Color;
+use sdl2::render::Renderer;
+
+use emulator::Emulator;
+use font::Font;
+use history::History;
+use library;
+use rom::Rom;
+use settings::Settings;
+use texture::CenteredTexture;
+
+/// How many games Continue shows
+const CONTINUE_MAX: usize = 24;
+
+/// A smart collection from config.toml, holding every game that passes all of its rules
+#[derive(Clone, Debug, PartialEq, RustcDecodable)]
+pub struct Rules {
+    pub name: String,
+    /// Only these systems, by their key in config.toml
+    pub systems: Option<Vec<String>>,
+    /// Text the metadata genre contains, ignoring case
+    pub genre: Option<String>,
+    /// Least number of players the metadata allows
+    pub players: Option<u32>,
+    /// DAT flags every game has, such as `Good`
+    pub flags: Option<Vec<String>>,
+    /// Region tag in the game's name, such as `USA`
+    pub region: Option<String>,
+    pub favorite: Option<bool>,
+    /// Games not played for this many days, including ones never played
+    pub unplayed_days: Option<u64>,
+}
+
+/// Most players of a metadata player count such as `2` or `1-4`
+fn max_players(players: &str) -> Option<u32> {
+    players.split(|c| c == '-' || c == ',').filter_map(|x| x.trim().parse().ok()).max()
+}
+
+impl Rules {
+    fn matches(&self, system: &str, rom: &Rom, history: &History, now: u64) -> bool {
+        let config = &rom.config;
+
+        if let Some(ref systems) = self.systems {
+            if ! systems.iter().any(|x| x == system) {
+                return false;
+            }
+        }
+
+        if let Some(ref genre) = self.genre {
+            match config.metadata.genre {
+                Some(ref x) if x.to_lowercase().contains(&genre.to_lowercase()) => (),
+                _ => return false
+            }
+        }
+
+        if let Some(players) = self.players {
+            match config.metadata.players.as_ref().and_then(|x| max_players(x)) {
+                Some(x) if x >= players => (),
+                _ => return false
+            }
+        }
+
+        if let Some(ref flags) = self.flags {
+            for flag in flags.iter() {
+                if ! config.flags.iter().any(|x| format!("{:?}", x).eq_ignore_ascii_case(flag)) {
+                    return false;
+                }
+            }
+        }
+
+        if let Some(ref region) = self.region {
+            if ! config.tags.regions.iter().any(|x| x.eq_ignore_ascii_case(region)) {
+                return false;
+            }
+        }
+
+        if let Some(favorite) = self.favorite {
+            if history.favorite(system, &config.name) != favorite {
+                return false;
+            }
+        }
+
+        if let Some(days) = self.unplayed_days {
+            if let Some(plays) = history.get(system, &config.name) {
+                if plays.last + days * 86400 > now {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// Games picked by hand for each named collection, by system and then ROM name
+#[derive(Default, RustcDecodable, RustcEncodable)]
+pub struct Picks {
+    pub collections: BTreeMap<String, BTreeMap<String, BTreeSet<String>>>,
+}
+
+impl Picks {
+    pub fn path() -> PathBuf {
+        let mut path = library::data_dir();
+        path.push("collections.json");
+        path
+    }
+
+    /// Load the picks, dropping collections and systems left without games
+    pub fn load() -> Picks {
+        let mut picks: Picks = library::load_json(&Picks::path());
+        picks.prune();
+        picks
+    }
+
+    pub fn save(&self) {
+        library::save_json(&Picks::path(), self);
+    }
+
+    pub fn contains(&self, collection: &str, system: &str, name: &str) -> bool {
+        self.collections.get(collection)
+            .and_then(|systems| systems.get(system))
+            .map(|names| names.contains(name))
+            .unwrap_or(false)
+    }
+
+    /// Collections from config.toml, then ones taken out of it that still hold games
+    pub fn names(&self, settings: &Settings) -> Vec<String> {
+        let mut names = settings.collections().to_vec();
+        for name in self.collections.keys() {
+            if ! names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        names
+    }
+
+    /// Add a game to a collection or remove it, saving right away
+    pub fn toggle(&mut self, collection: &str, system: &str, name: &str) {
+        {
+            let names = self.collections.entry(collection.to_string()).or_insert(BTreeMap::new())
+                .entry(system.to_string()).or_insert(BTreeSet::new());
+            if ! names.remove(name) {
+                names.insert(name.to_string());
+            }
+        }
+        self.prune();
+        self.save();
+    }
+
+    /// Forget empty collections, so one taken out of config.toml goes away with its last game
+    fn prune(&mut self) {
+        for systems in self.collections.values_mut() {
+            let empty: Vec<String> = systems.iter().filter(|&(_, names)| names.is_empty()).map(|(system, _)| system.clone()).collect();
+            for system in empty {
+                systems.remove(&system);
+            }
+        }
+        let empty: Vec<String> = self.collections.iter().filter(|&(_, systems)| systems.is_empty()).map(|(name, _)| name.clone()).collect();
+        for name in empty {
+            self.collections.remove(&name);
+        }
+    }
+}
+
+/// A list of ROMs from any system, shown on the Overview before the systems
+#[derive(Clone, Debug, PartialEq)]
+pub enum Collection {
+    /// Most recently played
+    Continue,
+    Favorites,
+    /// Named in config.toml, with games added from the ROM view
+    Picked(String),
+    Smart(Rules),
+}
+
+impl Collection {
+    /// Every collection, in the order of the Overview
+    pub fn all(settings: &Settings, picks: &Picks) -> Vec<Collection> {
+        let mut all = vec![Collection::Continue, Collection::Favorites];
+        // Collections taken out of config.toml are shown until their games are removed
+        for name in picks.names(settings) {
+            all.push(Collection::Picked(name));
+        }
+        for rules in settings.smart() {
+            all.push(Collection::Smart(rules.clone()));
+        }
+        all
+    }
+
+    pub fn name(&self) -> &str {
+        match *self {
+            Collection::Continue => "Continue",
+            Collection::Favorites => "Favorites",
+            Collection::Picked(ref name) => name,
+            Collection::Smart(ref rules) => &rules.name,
+        }
+    }
+
+    /// The ROMs in this collection, as (system, index into the system's ROMs)
+    pub fn roms(&self, emulators: &BTreeMap<String, Emulator>, history: &History, picks: &Picks) -> Vec<(String, usize)> {
+        let find = |system: &str, name: &str| -> Option<(String, usize)> {
+            emulators.get(system)
+                .and_then(|emulator| emulator.roms.iter().position(|rom| rom.config.name == name))
+                .map(|index| (system.to_string(), index))
+        };
+
+        match *self {
+            Collection::Continue => {
+                history.recent().into_iter()
+                    .filter_map(|(system, name, _)| find(system, name))
+                    .take(CONTINUE_MAX)
+                    .collect()
+            },
+            Collection::Favorites => {
+                let mut roms = Vec::new();
+                for (system, names) in history.favorites.iter() {
+                    for name in names.iter() {
+                        if let Some(rom) = find(system, name) {
+                            roms.push(rom);
+                        }
+                    }
+                }
+                roms
+            },
+            Collection::Picked(ref collection) => {
+                let mut roms = Vec::new();
+                if let Some(systems) = picks.collections.get(collection) {
+                    for (system, names) in systems.iter() {
+                        for name in names.iter() {
+                            if let Some(rom) = find(system, name) {
+                                roms.push(rom);
+                            }
+                        }
+                    }
+                }
+                roms
+            },
+            Collection::Smart(ref rules) => {
+                let now = library::now();
+                let mut roms = Vec::new();
+                for (system, emulator) in emulators.iter() {
+                    for (index, rom) in emulator.roms.iter().enumerate() {
+                        if rules.matches(system, rom, history, now) {
+                            roms.push((system.clone(), index));
+                        }
+                    }
+                }
+                roms
+            }
+        }
+    }
+
+    /// Draw the Overview tile, using the artwork of the first game
+    pub fn draw(&self, renderer: &mut Renderer, font: &Font, emulators: &BTreeMap<String, Emulator>, history: &History, picks: &Picks, x: i32, y: i32, w: i32, h: i32) {
+        let roms = self.roms(emulators, history, picks);
+
+        let text = format!("{}: {}", self.name(), roms.len());
+        let texture = CenteredTexture::new(font.render(&renderer, &text, Color::RGB(0, 0, 0)));
+        texture.draw(renderer, x + 8, y + 4, w - 16, 24);
+
+        if let Some(&(ref system, index)) = roms.first() {
+            if let Some(rom) = emulators.get(system).and_then(|emulator| emulator.roms.get(index)) {
+                rom.draw_image(renderer, x + 8, y + 8 + 32, w - 16, h - 32 - 16);
+            }
+        }
+    }
+}
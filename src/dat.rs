@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+
+use hash::Hashes;
+use ls;
+use rom::RomFlags;
+use scan;
+
+/// One ROM entry of a DAT file
+#[derive(Clone, Debug, Default)]
+pub struct DatRom {
+    pub game: String,
+    pub name: String,
+    pub size: u64,
+    pub crc32: String,
+    pub md5: String,
+    pub sha1: String,
+    pub status: String,
+}
+
+impl DatRom {
+    fn set(&mut self, key: &str, value: String) {
+        match key {
+            "name" => self.name = value,
+            "size" => self.size = value.parse().unwrap_or(0),
+            "crc" => self.crc32 = value.to_lowercase(),
+            "md5" => self.md5 = value.to_lowercase(),
+            "sha1" => self.sha1 = value.to_lowercase(),
+            "status" | "flags" => self.status = value,
+            _ => ()
+        }
+    }
+
+    /// True if the size and CRC32 match, which is enough to look at the other hashes
+    fn candidate(&self, hashes: &Hashes) -> bool {
+        ! self.crc32.is_empty() && self.crc32 == hashes.crc32 && self.size == hashes.size
+    }
+
+    /// Compare the strongest hash both sides have, the CRC32 if the MD5 and SHA1 were not computed
+    fn matches(&self, hashes: &Hashes) -> bool {
+        if ! self.sha1.is_empty() && ! hashes.sha1.is_empty() {
+            self.sha1 == hashes.sha1
+        } else if ! self.md5.is_empty() && ! hashes.md5.is_empty() {
+            self.md5 == hashes.md5
+        } else {
+            self.candidate(hashes)
+        }
+    }
+
+    fn flags(&self) -> Vec<RomFlags> {
+        match self.status.as_str() {
+            "baddump" | "nodump" => vec![RomFlags::Bad],
+            "verified" | "good" | "" => vec![RomFlags::Good],
+            _ => Vec::new()
+        }
+    }
+}
+
+/// A ROM identified by a DAT file
+#[derive(Clone, Debug)]
+pub struct Match {
+    pub dat: String,
+    pub game: String,
+    pub flags: Vec<RomFlags>,
+}
+
+fn unescape(string: &str) -> String {
+    string.replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Read an attribute from the inside of an XML tag
+fn attr(tag: &str, name: &str) -> Option<String> {
+    for quote in ['"', '\''].iter() {
+        let pat = format!(" {}={}", name, quote);
+        if let Some(p) = tag.find(&pat) {
+            let p = p + pat.len();
+            if let Some(n) = tag[p ..].find(*quote) {
+                return Some(unescape(&tag[p .. p + n]));
+            }
+        }
+    }
+    None
+}
+
+/// Split ClrMamePro text into quoted strings, parentheses and bare words
+fn tokens(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '"' {
+            let mut token = String::new();
+            while let Some(c) = chars.next() {
+                if c == '"' {
+                    break;
+                }
+                token.push(c);
+            }
+            tokens.push(token);
+        } else if c == '(' || c == ')' {
+            tokens.push(c.to_string());
+        } else if ! c.is_whitespace() {
+            let mut token = c.to_string();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '(' || c == ')' {
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+            tokens.push(token);
+        }
+    }
+
+    tokens
+}
+
+pub struct Dat {
+    pub name: String,
+    pub roms: Vec<DatRom>,
+}
+
+impl Dat {
+    /// Parse a Logiqx XML DAT, as used by No-Intro and Redump
+    pub fn parse_xml(xml: &str) -> Dat {
+        let mut dat = Dat {
+            name: String::new(),
+            roms: Vec::new(),
+        };
+
+        let mut game = String::new();
+        let mut in_header = false;
+        for part in xml.split('<') {
+            if part.starts_with("header") {
+                in_header = true;
+            } else if part.starts_with("/header") {
+                in_header = false;
+            } else if in_header && part.starts_with("name>") {
+                dat.name = unescape(part["name>".len() ..].trim());
+            } else if part.starts_with("game ") || part.starts_with("machine ") {
+                game = attr(part, "name").unwrap_or(String::new());
+            } else if part.starts_with("rom ") {
+                let mut rom = DatRom::default();
+                rom.game = game.clone();
+                for key in ["name", "size", "crc", "md5", "sha1", "status"].iter() {
+                    if let Some(value) = attr(part, key) {
+                        rom.set(key, value);
+                    }
+                }
+                dat.roms.push(rom);
+            }
+        }
+
+        dat
+    }
+
+    /// Parse a ClrMamePro text DAT
+    pub fn parse_clrmamepro(text: &str) -> Dat {
+        let mut dat = Dat {
+            name: String::new(),
+            roms: Vec::new(),
+        };
+
+        let tokens = tokens(text);
+        let mut block = String::new();
+        let mut game = String::new();
+        let mut depth = 0;
+        let mut i = 0;
+        while i < tokens.len() {
+            let token = &tokens[i];
+            if token == "(" {
+                depth += 1;
+                i += 1;
+            } else if token == ")" {
+                depth -= 1;
+                i += 1;
+            } else if depth == 0 {
+                block = token.clone();
+                game.clear();
+                i += 1;
+            } else if token == "rom" && tokens.get(i + 1).map(|x| x == "(").unwrap_or(false) {
+                let mut rom = DatRom::default();
+                rom.game = game.clone();
+                i += 2;
+                while i + 1 < tokens.len() && tokens[i] != ")" {
+                    rom.set(&tokens[i], tokens[i + 1].clone());
+                    i += 2;
+                }
+                i += 1;
+                dat.roms.push(rom);
+            } else if token == "name" && depth == 1 {
+                let value = tokens.get(i + 1).cloned().unwrap_or(String::new());
+                if block == "clrmamepro" {
+                    dat.name = value;
+                } else {
+                    game = value;
+                }
+                i += 2;
+            } else {
+                i += 1;
+            }
+        }
+
+        dat
+    }
+
+    pub fn load(path: &str) -> Option<Dat> {
+        let mut text = String::new();
+        match File::open(path).and_then(|mut file| file.read_to_string(&mut text)) {
+            Ok(_) => {
+                let mut dat = if text.trim_start().starts_with('<') {
+                    Dat::parse_xml(&text)
+                } else {
+                    Dat::parse_clrmamepro(&text)
+                };
+
+                if dat.name.is_empty() {
+                    dat.name = scan::file_name(path);
+                }
+
+                Some(dat)
+            },
+            Err(err) => {
+                println!("{}: {}", path, err);
+                None
+            }
+        }
+    }
+}
+
+/// Where an entry is, as (DAT, ROM) indexes into `Dats::dats`
+type Entry = (usize, usize);
+
+/// The DAT files that apply to one system
+pub struct Dats {
+    pub dats: Vec<Dat>,
+    /// Entries by size and CRC32, so ROMs are not compared with every entry
+    by_crc32: HashMap<(u64, String), Vec<Entry>>,
+    /// Entries with only an MD5 or SHA1, by size
+    by_size: HashMap<u64, Vec<Entry>>,
+}
+
+impl Dats {
+    /// Load DATs from a directory, keeping those named in `names`, or all of them if it is empty.
+    ///
+    /// A DAT matches a name if its header name is equal, or its file name is the name followed
+    /// by a version, like "Nintendo - Game Boy (20190101-000000).dat".
+    pub fn load(dir: &str, names: &[String]) -> Dats {
+        let mut dats = Vec::new();
+
+        for path in ls(dir) {
+            let ext = scan::extension(&path);
+            if ext != "dat" && ext != "xml" {
+                continue;
+            }
+
+            if let Some(dat) = Dat::load(&path) {
+                let file_name = scan::file_name(&path);
+                let wanted = names.is_empty() || names.iter().any(|name| {
+                    dat.name == *name
+                    || file_name == format!("{}.{}", name, ext)
+                    || file_name.starts_with(&format!("{} (", name))
+                });
+                if wanted {
+                    dats.push(dat);
+                }
+            }
+        }
+
+        let mut by_crc32 = HashMap::new();
+        let mut by_size = HashMap::new();
+        for (i, dat) in dats.iter().enumerate() {
+            for (j, rom) in dat.roms.iter().enumerate() {
+                if ! rom.crc32.is_empty() {
+                    by_crc32.entry((rom.size, rom.crc32.clone())).or_insert(Vec::new()).push((i, j));
+                } else if ! rom.md5.is_empty() || ! rom.sha1.is_empty() {
+                    by_size.entry(rom.size).or_insert(Vec::new()).push((i, j));
+                }
+            }
+        }
+
+        Dats {
+            dats: dats,
+            by_crc32: by_crc32,
+            by_size: by_size,
+        }
+    }
+
+    /// Entries that may match a ROM, in the order of the DATs
+    fn candidates(&self, hashes: &Hashes) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        if let Some(found) = self.by_crc32.get(&(hashes.size, hashes.crc32.clone())) {
+            entries.extend(found.iter().cloned());
+        }
+        if let Some(found) = self.by_size.get(&hashes.size) {
+            entries.extend(found.iter().cloned());
+        }
+        entries.sort();
+        entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dats.is_empty()
+    }
+
+    /// True if the MD5 and SHA1 of a ROM are needed to identify it, because its size and CRC32 match
+    /// an entry, or its size matches one that only has an MD5 or SHA1
+    pub fn wants_full(&self, hashes: &Hashes) -> bool {
+        hashes.size > 0 && ! hashes.full() && ! self.candidates(hashes).is_empty()
+    }
+
+    /// Find the DAT entry with the same hashes
+    pub fn find(&self, hashes: &Hashes) -> Option<&DatRom> {
+        self.find_dat(hashes).map(|x| x.1)
+    }
+
+    fn find_dat(&self, hashes: &Hashes) -> Option<(&Dat, &DatRom)> {
+        if hashes.size == 0 {
+            return None;
+        }
+
+        self.candidates(hashes).into_iter()
+            .map(|(i, j)| (&self.dats[i], &self.dats[i].roms[j]))
+            .find(|&(_, rom)| rom.matches(hashes))
+    }
+
+    /// Identify a ROM by its hashes.
+    ///
+    /// A file whose name is in a DAT but whose hashes are not may be a hack, a translation or a
+    /// headered dump as well as a bad one, so names alone identify nothing.
+    pub fn identify(&self, hashes: &Hashes) -> Option<Match> {
+        self.find_dat(hashes).map(|(dat, rom)| Match {
+            dat: dat.name.clone(),
+            game: rom.game.clone(),
+            flags: rom.flags(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clrmamepro_tokens() {
+        assert_eq!(tokens("game ( name \"Super Mario Land (World)\" size 65536)"),
+            vec!["game", "(", "name", "Super Mario Land (World)", "size", "65536", ")"]);
+    }
+
+    #[test]
+    fn clrmamepro() {
+        let dat = Dat::parse_clrmamepro("
+            clrmamepro (
+                name \"Nintendo - Game Boy\"
+                version 20190101-000000
+            )
+
+            game (
+                name \"Tetris (World) (Rev 1)\"
+                rom ( name \"Tetris (World) (Rev 1).gb\" size 32768 crc 46DF91AD md5 084F1E457749CDEC86183189BD88CE69 flags verified )
+            )
+
+            game (
+                name \"Tetris (Beta)\"
+                rom ( name \"Tetris (Beta).gb\" size 32768 crc 0000abcd flags baddump )
+            )
+        ");
+
+        assert_eq!(dat.name, "Nintendo - Game Boy");
+        assert_eq!(dat.roms.len(), 2);
+        assert_eq!(dat.roms[0].game, "Tetris (World) (Rev 1)");
+        assert_eq!(dat.roms[0].name, "Tetris (World) (Rev 1).gb");
+        assert_eq!(dat.roms[0].size, 32768);
+        assert_eq!(dat.roms[0].crc32, "46df91ad");
+        assert_eq!(dat.roms[0].md5, "084f1e457749cdec86183189bd88ce69");
+        assert_eq!(dat.roms[0].flags(), vec![RomFlags::Good]);
+        assert_eq!(dat.roms[1].game, "Tetris (Beta)");
+        assert_eq!(dat.roms[1].flags(), vec![RomFlags::Bad]);
+    }
+
+    #[test]
+    fn logiqx() {
+        let dat = Dat::parse_xml("<?xml version=\"1.0\"?>
+            <datafile>
+                <header><name>Nintendo - Game Boy</name></header>
+                <game name=\"Mario &amp; Yoshi (Europe)\">
+                    <rom name='Mario &amp; Yoshi (Europe).gb' size=\"32768\" crc=\"F2C7AB2E\" sha1=\"ABCDEF\"/>
+                </game>
+            </datafile>");
+
+        assert_eq!(dat.name, "Nintendo - Game Boy");
+        assert_eq!(dat.roms.len(), 1);
+        assert_eq!(dat.roms[0].game, "Mario & Yoshi (Europe)");
+        assert_eq!(dat.roms[0].name, "Mario & Yoshi (Europe).gb");
+        assert_eq!(dat.roms[0].crc32, "f2c7ab2e");
+        assert_eq!(dat.roms[0].sha1, "abcdef");
+        assert_eq!(dat.roms[0].status, "");
+    }
+}
@@ -11,6 +11,8 @@ use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
 
 use rom::{Progress, RomConfig, RomFlags};
+use rom::tags::Tags;
+use scan;
 
 trait FindFrom {
     fn find_from(&self, pat: &str, start: usize) -> Option<usize>;
@@ -139,6 +141,7 @@ fn parse(html: &str, roms: &mut Vec<RomConfig>) -> Page {
             }
 
             if entry.file != "No Roms" {
+                entry.tags = Tags::parse(&scan::stem(&entry.file));
                 roms.push(entry);
                 entry = RomConfig::default();
             }
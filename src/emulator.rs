@@ -6,57 +6,276 @@ use sdl2::render::Renderer;
 
 use sdl2_image::LoadTexture;
 
+use archive;
+use audit::{self, Audit};
+use browse::Browse;
 use doperoms;
 use font::Font;
+use group::{Priority, Versions};
+use library::{Change, Layout, Scan};
 use rom::{Progress, Rom, RomConfig};
-use ls;
+use rom::overrides::Overrides;
+use scan;
+use settings::Settings;
+use template::Vars;
 use texture::{CenteredTexture, ScaledTexture};
 
+/// One way of running a system's games, such as a RetroArch core or a standalone emulator
+#[derive(Clone, Debug, RustcDecodable)]
+pub struct Profile {
+    pub name: String,
+    /// Emulator to run, which may use the placeholders of `args`
+    pub program: String,
+    /// Arguments, with placeholders like `%r` for the ROM file, see `template::Vars`
+    pub args: Vec<String>,
+}
+
+/// A launch line with its placeholders expanded and the game's overrides applied
+pub struct Launch {
+    pub program: String,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub dir: Option<String>,
+}
+
+impl Launch {
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        for &(ref key, ref value) in self.env.iter() {
+            command.env(key, value);
+        }
+        if let Some(ref dir) = self.dir {
+            command.current_dir(dir);
+        }
+        command
+    }
+
+    /// The command as it could be typed in a shell, to show it
+    pub fn line(&self) -> String {
+        fn quote(text: &str) -> String {
+            if ! text.is_empty() && text.chars().all(|c| c.is_alphanumeric() || "-_=+/.,:%@".contains(c)) {
+                text.to_string()
+            } else {
+                format!("'{}'", text.replace('\'', "'\\''"))
+            }
+        }
+
+        let mut parts = Vec::new();
+        if let Some(ref dir) = self.dir {
+            parts.push(format!("cd {} &&", quote(dir)));
+        }
+        for &(ref key, ref value) in self.env.iter() {
+            parts.push(format!("{}={}", key, quote(value)));
+        }
+        parts.push(quote(&self.program));
+        for arg in self.args.iter() {
+            parts.push(quote(arg));
+        }
+        parts.join(" ")
+    }
+}
+
 #[derive(RustcDecodable)]
 pub struct EmulatorConfig {
     pub name: String,
     pub image: String,
     pub roms: String,
-    pub program: String,
-    pub args: Vec<String>,
+    /// More ROM directories, such as network shares or removable drives, searched after `roms`
+    pub roots: Option<Vec<String>>,
+    /// Emulator of the profile named `Default`, which may be left out when there are `profiles`
+    pub program: Option<String>,
+    pub args: Option<Vec<String>>,
+    /// More profiles, as `[[<system>.profiles]]` tables
+    pub profiles: Option<Vec<Profile>>,
+    /// Name of the profile used by games not pinned to another, the first one if left out
+    pub profile: Option<String>,
     pub doperoms: String,
+    pub extensions: Option<Vec<String>>,
+    /// Either `per_game_dir`, the default, or `flat`
+    pub layout: Option<String>,
+    /// Artwork folder for flat layouts, `<roms>/images` by default
+    pub artwork: Option<String>,
+    /// Keep ROMs in their archives, extracting them to a cache only to launch
+    pub compressed: Option<bool>,
+    /// Names of the DAT files that describe this system
+    pub dats: Option<Vec<String>>,
+    /// Commands run before launching a game of this system, after the global ones
+    pub pre_launch: Option<Vec<Vec<String>>>,
+    /// Commands run after the emulator exits, before the global ones
+    pub post_exit: Option<Vec<Vec<String>>>,
+}
+
+impl EmulatorConfig {
+    /// Accepted ROM extensions, empty if any file is accepted
+    pub fn extensions(&self) -> Vec<String> {
+        let mut extensions = self.extensions.clone().unwrap_or(Vec::new());
+        if self.compressed() && ! extensions.is_empty() {
+            for ext in archive::EXTENSIONS.iter() {
+                extensions.push(ext.to_string());
+            }
+        }
+        extensions
+    }
+
+    /// Accepted extensions of the ROM inside an archive
+    pub fn rom_extensions(&self) -> Vec<String> {
+        self.extensions.clone().unwrap_or(Vec::new())
+    }
+
+    /// Every ROM directory, in order of precedence
+    pub fn roots(&self) -> Vec<String> {
+        let mut roots = vec![self.roms.clone()];
+        if let Some(ref extra) = self.roots {
+            roots.extend(extra.iter().cloned());
+        }
+        roots
+    }
+
+    pub fn layout(&self) -> Layout {
+        match self.layout.as_ref().map(|x| x.as_str()) {
+            Some("flat") => Layout::Flat(self.artwork()),
+            Some("per_game_dir") | None => Layout::PerGameDir,
+            Some(other) => {
+                println!("{}: unknown layout {}, using per_game_dir", self.name, other);
+                Layout::PerGameDir
+            }
+        }
+    }
+
+    pub fn artwork(&self) -> String {
+        match self.artwork {
+            Some(ref artwork) => artwork.clone(),
+            None => format!("{}/images", self.roms.trim_end_matches('/'))
+        }
+    }
+
+    /// Where a downloaded ROM and its artwork are saved, as (name, file, image)
+    pub fn download_paths(&self, name: &str, file: &str) -> (String, String, String) {
+        let roms = self.roms.trim_end_matches('/');
+        match self.layout() {
+            Layout::PerGameDir => (
+                name.to_string(),
+                format!("{}/{}/{}", roms, name, file),
+                format!("{}/{}/image.jpg", roms, name)
+            ),
+            Layout::Flat(artwork) => (
+                scan::stem(file),
+                format!("{}/{}", roms, file),
+                format!("{}/{}.jpg", artwork.trim_end_matches('/'), scan::stem(file))
+            )
+        }
+    }
+
+    /// Every launch profile, starting with the one made of `program` and `args`
+    pub fn profiles(&self) -> Vec<Profile> {
+        let mut profiles = Vec::new();
+        if let Some(ref program) = self.program {
+            profiles.push(Profile {
+                name: "Default".to_string(),
+                program: program.clone(),
+                args: self.args.clone().unwrap_or(Vec::new()),
+            });
+        }
+        if let Some(ref extra) = self.profiles {
+            profiles.extend(extra.iter().cloned());
+        }
+        profiles
+    }
+
+    /// Profile with this name, falling back to the system default
+    pub fn find_profile(&self, name: Option<&str>) -> Option<Profile> {
+        let profiles = self.profiles();
+        let default = self.profile.as_ref().map(|x| x.as_str());
+        for name in name.iter().chain(default.iter()) {
+            if let Some(profile) = profiles.iter().find(|x| x.name == *name) {
+                return Some(profile.clone());
+            }
+        }
+        profiles.into_iter().next()
+    }
+
+    /// Unpin a game from a profile this system no longer has, saying so once as the game is loaded
+    pub fn check_pin(&self, config: &mut RomConfig) {
+        let missing = match config.profile {
+            Some(ref name) if ! self.profiles().iter().any(|x| x.name == *name) => Some(name.clone()),
+            _ => None
+        };
+        if let Some(name) = missing {
+            println!("{}: no profile named {} for {}, using the default", self.name, name, config.name);
+            config.profile = None;
+        }
+    }
+
+    pub fn compressed(&self) -> bool {
+        self.compressed.unwrap_or(false)
+    }
+
+    /// Names of this system's DAT files, empty if every DAT applies
+    pub fn dats(&self) -> &[String] {
+        match self.dats {
+            Some(ref dats) => &dats[..],
+            None => &[]
+        }
+    }
+
+    pub fn pre_launch(&self) -> &[Vec<String>] {
+        match self.pre_launch {
+            Some(ref hooks) => hooks,
+            None => &[]
+        }
+    }
+
+    pub fn post_exit(&self) -> &[Vec<String>] {
+        match self.post_exit {
+            Some(ref hooks) => hooks,
+            None => &[]
+        }
+    }
 }
 
 pub struct Emulator {
+    pub key: String,
     name: CenteredTexture,
     image: ScaledTexture,
     pub roms: Vec<Rom>,
+    pub scan: Scan,
     pub doperoms: Option<doperoms::List>,
     pub downloads: Vec<RomConfig>,
+    pub audit_job: Option<audit::Job>,
+    /// The last audit, or why it failed
+    pub audit: Option<Result<Audit, String>>,
+    pub browse: Browse,
+    priority: Priority,
+    /// Versions of each game in `roms`, grouped again when the ROMs change
+    pub versions: Versions,
+    /// Versions of each game in `downloads`
+    pub download_versions: Versions,
     pub config: EmulatorConfig
 }
 
 impl Emulator {
-    pub fn new(renderer: &Renderer, font: &Font, config: EmulatorConfig) -> Emulator {
-        let mut roms = Vec::new();
-        for path in ls(&config.roms) {
-            let mut rom = String::new();
-            for file in ls(&path) {
-                if file.ends_with(".jpg") || file.ends_with(".7z") || file.ends_with(".zip") {
-                } else {
-                    rom = file;
-                }
+    pub fn new(renderer: &Renderer, font: &Font, settings: &Settings, key: &str, config: EmulatorConfig) -> Emulator {
+        if let Some(ref name) = config.profile {
+            if ! config.profiles().iter().any(|x| x.name == *name) {
+                println!("{}: no profile named {}", config.name, name);
             }
-
-            roms.push(Rom::new(renderer, RomConfig {
-                name: path.replace(&config.roms, "").trim_matches('/').to_string(),
-                file: rom,
-                image: path.to_string() + "/image.jpg",
-                flags: Vec::new(),
-            }));
         }
 
         Emulator {
+            key: key.to_string(),
             name: CenteredTexture::new(font.render(&renderer, &config.name, Color::RGB(0, 0, 0))),
             image: ScaledTexture::new(renderer.load_texture(&Path::new(&config.image)).unwrap()),
-            roms: roms,
+            roms: Vec::new(),
+            scan: Scan::new(key, &config, settings),
             doperoms: Some(doperoms::List::new(&config.doperoms)),
             downloads: Vec::new(),
+            audit_job: None,
+            audit: None,
+            browse: Browse::load(key),
+            priority: Priority::new(settings),
+            versions: Versions::default(),
+            download_versions: Versions::default(),
             config: config
         }
     }
@@ -64,22 +283,118 @@ impl Emulator {
     pub fn draw(&self, renderer: &mut Renderer, font: &Font, x: i32, y: i32, w: i32, h: i32) {
         self.name.draw(renderer, x + 8, y + 4, w - 16, 24);
         self.image.draw(renderer, x + 8, y + 8 + 32, w - 16, h - 32 - 16);
+
+        if self.scan.scanning() {
+            let text = match self.scan.progress() {
+                Progress::Error(error) => format!("Scanning: {}", error),
+                _ => format!("Scanning: {} ROMs", self.roms.len() + self.scan.pending())
+            };
+            let texture = CenteredTexture::new(font.render(&renderer, &text, Color::RGB(0, 0, 0)));
+            texture.draw(renderer, x + 8, y + h - 32, w - 16, 24);
+        }
+    }
+
+    /// True if the ROM has to be extracted to the cache before it can be run
+    pub fn needs_extract(&self, rom: &Rom) -> bool {
+        self.config.compressed() && archive::is_archive(&rom.config.file)
+    }
+
+    /// Where the playlist of a multi-disc game goes, if it has several discs and the system accepts m3u
+    pub fn playlist(&self, rom: &Rom) -> Option<String> {
+        let accepts_m3u = self.config.rom_extensions().iter().any(|x| x.trim_matches('.').to_lowercase() == "m3u");
+        if rom.config.discs.len() > 1 && accepts_m3u {
+            Some(scan::playlist_path(&self.key, &rom.config.name))
+        } else {
+            None
+        }
+    }
+
+    /// Profile to run a ROM with: the one asked for, else the one the game is pinned to, else the system default
+    pub fn profile(&self, rom: &Rom, asked: Option<&str>) -> Option<Profile> {
+        self.config.find_profile(asked.or(rom.config.profile.as_ref().map(|x| x.as_str())))
     }
 
-    pub fn run(&self, rom: &Rom) -> Command {
-        let mut command = Command::new(&self.config.program);
-        for arg in self.config.args.iter() {
-            if arg == "%r" {
-                command.arg(&rom.config.file);
-            }else{
-                command.arg(arg);
+    /// Launch line of a ROM, with `file` being the ROM or its extracted copy, and the overrides of the game applied
+    pub fn resolve(&self, profile: &Profile, overrides: &Overrides, name: &str, file: &str) -> Launch {
+        let vars = Vars::new(&self.key, name, file);
+
+        Launch {
+            program: vars.expand(overrides.program.as_ref().unwrap_or(&profile.program)),
+            args: overrides.args(&profile.args).iter().filter_map(|x| vars.arg(x)).collect(),
+            env: match overrides.env {
+                Some(ref env) => env.iter().map(|(key, value)| (key.clone(), vars.expand(value))).collect(),
+                None => Vec::new()
+            },
+            dir: overrides.dir.as_ref().map(|x| vars.expand(x)),
+        }
+    }
+
+    /// Command to run a ROM, with `file` being the ROM or its extracted copy
+    pub fn run(&self, profile: &Profile, name: &str, file: &str) -> Command {
+        let overrides = match self.roms.iter().find(|rom| rom.config.name == name) {
+            Some(rom) => rom.config.overrides.clone(),
+            None => Overrides::default()
+        };
+
+        self.resolve(profile, &overrides, name, file).command()
+    }
+
+    /// ROMs that finished extracting to the launch cache, as (profile, name, file) ready to run
+    pub fn take_launches(&mut self) -> Vec<(Profile, String, String)> {
+        let mut launches = Vec::new();
+
+        for rom in self.roms.iter_mut() {
+            let take_launch = if let Some(ref launch) = rom.launch {
+                match launch.progress() {
+                    Progress::Complete => true,
+                    _ => false
+                }
+            } else {
+                false
+            };
+
+            if take_launch {
+                if let Some(launch) = rom.launch.take() {
+                    if let Some(file) = launch.result() {
+                        let asked = rom.launch_profile.take();
+                        let name = asked.as_ref().or(rom.config.profile.as_ref()).map(|x| x.as_str());
+                        match self.config.find_profile(name) {
+                            Some(profile) => launches.push((profile, rom.config.name.clone(), file)),
+                            None => println!("{}: no program to run", self.config.name)
+                        }
+                    }
+                }
             }
         }
 
-        command
+        launches
     }
 
     pub fn update(&mut self, renderer: &Renderer) {
+        // Textures have to be loaded here, so only a few changes are applied per frame
+        let changes = self.scan.take(8);
+        // Downloads are added to the ROMs from the download list, outside of here
+        let regroup = ! changes.is_empty() || self.versions.len() != self.roms.len();
+        for change in changes {
+            match change {
+                Change::Update(mut config) => {
+                    self.config.check_pin(&mut config);
+                    if let Some(rom) = self.roms.iter_mut().find(|rom| rom.config.name == config.name) {
+                        // Downloads update their own ROM when they finish
+                        if rom.doperoms.is_none() && rom.image_dl.is_none() {
+                            rom.reload(renderer, config);
+                        }
+                        continue;
+                    }
+
+                    self.roms.push(Rom::new(renderer, config));
+                },
+                Change::Remove(name) => {
+                    self.roms.retain(|rom| rom.config.name != name || rom.doperoms.is_some());
+                }
+            }
+        }
+
         let take_doperoms = if let Some(ref doperoms) = self.doperoms {
             match doperoms.progress() {
                 Progress::Complete => true,
@@ -95,10 +410,33 @@ impl Emulator {
                     self.downloads.push(config);
                 }
             }
+
+            let downloads = &self.downloads;
+            self.download_versions = self.priority.versions(downloads.len(), |index| &downloads[index]);
+        }
+
+        let take_audit_job = if let Some(ref audit_job) = self.audit_job {
+            match audit_job.progress() {
+                Progress::Complete | Progress::Error(_) => true,
+                _ => false
+            }
+        } else {
+            false
+        };
+
+        if take_audit_job {
+            if let Some(audit_job) = self.audit_job.take() {
+                self.audit = Some(audit_job.result());
+            }
         }
 
         for mut rom in self.roms.iter_mut() {
-            rom.update(renderer);
+            rom.update(renderer, &self.config);
+        }
+
+        if regroup {
+            let roms = &self.roms;
+            self.versions = self.priority.versions(roms.len(), |index| &roms[index].config);
         }
     }
 }
@@ -20,4 +20,10 @@ impl Font {
         let surface = self.font.render(text, sdl2_ttf::blended(color)).unwrap();
         return renderer.create_texture_from_surface(&surface).unwrap();
     }
+
+    /// Render text broken into lines no wider than `width` pixels
+    pub fn render_wrapped(&self, renderer: &Renderer, text: &str, color: Color, width: u32) -> Texture {
+        let surface = self.font.render(text, sdl2_ttf::blended_wrapped(color, width)).unwrap();
+        return renderer.create_texture_from_surface(&surface).unwrap();
+    }
 }
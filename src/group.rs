@@ -0,0 +1,173 @@
+//! One game, one ROM: grouping the versions of a game and picking the one to play
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use rom::{RomConfig, RomFlags};
+use settings::Settings;
+
+/// Tags of versions that were never released as they are
+const UNRELEASED: &'static [&'static str] = &["Beta", "Demo", "Kiosk", "Pirate", "Proto", "Sample"];
+
+/// A revision or version split into runs of digits and the rest, so `Rev 10` comes after `Rev 2`
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Part {
+    Number(u64),
+    Text(String),
+}
+
+fn parts(tag: &Option<String>) -> Option<Vec<Part>> {
+    tag.as_ref().map(|tag| {
+        let mut parts = Vec::new();
+        let mut rest = tag.as_str();
+        while let Some(first) = rest.chars().next() {
+            let digits = first.is_ascii_digit();
+            let end = rest.find(|c: char| c.is_ascii_digit() != digits).unwrap_or(rest.len());
+            parts.push(match rest[.. end].parse() {
+                Ok(number) if digits => Part::Number(number),
+                _ => Part::Text(rest[.. end].to_lowercase())
+            });
+            rest = &rest[end ..];
+        }
+        parts
+    })
+}
+
+/// Versions of every game in a list, grouped once so each frame only has to pick from them
+#[derive(Default)]
+pub struct Versions {
+    groups: Vec<Vec<usize>>,
+    /// Which group each index is in
+    group_of: Vec<usize>,
+}
+
+impl Versions {
+    /// How many indexes were grouped, to tell when the list has grown since
+    pub fn len(&self) -> usize {
+        self.group_of.len()
+    }
+
+    /// Every version of the game at `index`, preferred first
+    pub fn of(&self, index: usize) -> Vec<usize> {
+        match self.group_of.get(index) {
+            Some(&group) => self.groups[group].clone(),
+            None => vec![index]
+        }
+    }
+
+    /// The games of the indexes in `order`, in the order of their first index, each starting with its preferred version.
+    ///
+    /// Indexes added since grouping are games of their own.
+    pub fn select(&self, order: &[usize]) -> Vec<Vec<usize>> {
+        let mut shown: Vec<Option<usize>> = vec![None; self.groups.len()];
+        let mut selected: Vec<Vec<usize>> = Vec::new();
+        for &index in order.iter() {
+            match self.group_of.get(index) {
+                Some(&group) => match shown[group] {
+                    Some(i) => selected[i].push(index),
+                    None => {
+                        shown[group] = Some(selected.len());
+                        selected.push(vec![index]);
+                    }
+                },
+                None => selected.push(vec![index])
+            }
+        }
+
+        // Versions keep their preferred order, whatever order they were shown in
+        for versions in selected.iter_mut() {
+            if let Some(&group) = self.group_of.get(versions[0]) {
+                let group = &self.groups[group];
+                versions.sort_by_key(|index| group.iter().position(|x| x == index));
+            }
+        }
+        selected
+    }
+}
+
+/// Region and language preferences, from the `[emulition]` table of config.toml
+pub struct Priority {
+    regions: Vec<String>,
+    languages: Vec<String>,
+}
+
+impl Priority {
+    pub fn new(settings: &Settings) -> Priority {
+        Priority {
+            regions: settings.regions(),
+            languages: settings.languages(),
+        }
+    }
+
+    /// Lower is better: good dumps of released versions first, then region, language and the latest revision
+    fn rank(&self, config: &RomConfig) -> (usize, usize, usize, Reverse<Option<Vec<Part>>>, Reverse<Option<Vec<Part>>>) {
+        let tags = &config.tags;
+
+        let quality = if config.flags.iter().any(|x| *x != RomFlags::Good && *x != RomFlags::Alternate) {
+            2
+        } else if tags.other.iter().any(|x| UNRELEASED.iter().any(|y| x.starts_with(y))) {
+            1
+        } else {
+            0
+        };
+
+        let region = tags.regions.iter()
+            .filter_map(|x| self.regions.iter().position(|y| y == x))
+            .min()
+            .unwrap_or(self.regions.len());
+
+        let language = tags.languages.iter()
+            .filter_map(|x| self.languages.iter().position(|y| x == y || (x.starts_with(y.as_str()) && x[y.len() ..].starts_with('-'))))
+            .min()
+            .unwrap_or(self.languages.len());
+
+        (quality, region, language, Reverse(parts(&tags.revision)), Reverse(parts(&tags.version)))
+    }
+
+    /// Group the ROMs at `indexes` that are versions of the same game, by their title without tags.
+    ///
+    /// Groups keep the order of their first ROM, and each starts with its preferred version.
+    fn group<'a, F: Fn(usize) -> &'a RomConfig>(&self, indexes: &[usize], config: F) -> Vec<Vec<usize>> {
+        let mut keys: BTreeMap<String, usize> = BTreeMap::new();
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for &index in indexes.iter() {
+            let key = config(index).display_title().to_lowercase();
+            match keys.get(&key).cloned() {
+                Some(i) => groups[i].push(index),
+                None => {
+                    keys.insert(key, groups.len());
+                    groups.push(vec![index]);
+                }
+            }
+        }
+
+        // Each ROM is ranked once, rather than on every comparison
+        for group in groups.iter_mut() {
+            if group.len() > 1 {
+                let mut ranked: Vec<_> = group.iter().map(|&index| (self.rank(config(index)), config(index).title(), index)).collect();
+                ranked.sort();
+                *group = ranked.into_iter().map(|(_, _, index)| index).collect();
+            }
+        }
+
+        groups
+    }
+
+    /// Group all `len` indexes of a list, to pick from with `Versions::select`
+    pub fn versions<'a, F: Fn(usize) -> &'a RomConfig>(&self, len: usize, config: F) -> Versions {
+        let all: Vec<usize> = (0 .. len).collect();
+        let groups = self.group(&all, config);
+
+        let mut group_of = vec![0; len];
+        for (i, group) in groups.iter().enumerate() {
+            for &index in group.iter() {
+                group_of[index] = i;
+            }
+        }
+
+        Versions {
+            groups: groups,
+            group_of: group_of,
+        }
+    }
+}
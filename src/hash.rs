@@ -0,0 +1,125 @@
+extern crate crc32fast;
+extern crate md5;
+extern crate sevenz_rust;
+extern crate sha1;
+extern crate zip;
+
+use std::fs::File;
+use std::io::{self, Read};
+
+use scan;
+
+/// The hashes DAT files identify ROMs by, as lowercase hex
+#[derive(Clone, Debug, Default, PartialEq, RustcDecodable, RustcEncodable)]
+pub struct Hashes {
+    pub size: u64,
+    pub crc32: String,
+    pub md5: String,
+    pub sha1: String,
+}
+
+impl Hashes {
+    /// Hash a stream, with MD5 and SHA1 only if `full`, since DAT lookups start with the size and CRC32
+    pub fn read<R: Read>(mut reader: R, full: bool) -> io::Result<Hashes> {
+        let mut size = 0;
+        let mut crc32 = crc32fast::Hasher::new();
+        let mut md5 = md5::Context::new();
+        let mut sha1 = sha1::Sha1::new();
+
+        let mut bytes = vec![0; 65536];
+        loop {
+            let count = reader.read(&mut bytes)?;
+            if count == 0 {
+                break;
+            }
+            size += count as u64;
+            crc32.update(&bytes[.. count]);
+            if full {
+                md5.consume(&bytes[.. count]);
+                sha1.update(&bytes[.. count]);
+            }
+        }
+
+        Ok(Hashes {
+            size: size,
+            crc32: format!("{:08x}", crc32.finalize()),
+            md5: if full { format!("{:x}", md5.compute()) } else { String::new() },
+            sha1: if full { sha1.digest().to_string() } else { String::new() },
+        })
+    }
+
+    /// True if the MD5 and SHA1 were computed along with the CRC32
+    pub fn full(&self) -> bool {
+        ! self.sha1.is_empty()
+    }
+
+    pub fn file(path: &str, full: bool) -> io::Result<Hashes> {
+        Hashes::read(File::open(path)?, full)
+    }
+
+    /// Hash every file inside a zip archive, without extracting it to disk.
+    ///
+    /// Unless `full`, the sizes and CRC32s stored in the archive are used without reading the files.
+    pub fn zip(path: &str, full: bool) -> io::Result<Vec<(String, Hashes)>> {
+        let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+
+        let mut entries = Vec::new();
+        for i in 0 .. archive.len() {
+            let file = archive.by_index(i)?;
+            if file.is_file() {
+                let name = file.name().to_string();
+                let hashes = if full {
+                    Hashes::read(file, full)?
+                } else {
+                    Hashes {
+                        size: file.size(),
+                        crc32: format!("{:08x}", file.crc32()),
+                        ..Hashes::default()
+                    }
+                };
+                entries.push((name, hashes));
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Hash every file inside a 7z archive, without extracting it to disk
+    pub fn sevenz(path: &str, full: bool) -> io::Result<Vec<(String, Hashes)>> {
+        let mut entries = Vec::new();
+
+        sevenz_rust::SevenZReader::open(path, sevenz_rust::Password::empty())
+            .and_then(|mut archive| archive.for_each_entries(|entry, reader| {
+                if ! entry.is_directory() {
+                    entries.push((entry.name().to_string(), Hashes::read(reader, full)?));
+                }
+                Ok(true)
+            }))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}", err)))?;
+
+        Ok(entries)
+    }
+}
+
+/// Hash a ROM, looking inside zip and 7z archives for the largest file, and through descriptors for the first track.
+///
+/// Returns the name of the file that was hashed along with its hashes, with MD5 and SHA1 only if `full`.
+pub fn rom(path: &str, full: bool) -> io::Result<(String, Hashes)> {
+    let ext = scan::extension(path);
+    if ext == "zip" || ext == "7z" {
+        let mut entries = if ext == "zip" {
+            Hashes::zip(path, full)?
+        } else {
+            Hashes::sevenz(path, full)?
+        };
+        entries.sort_by_key(|entry| entry.1.size);
+        match entries.pop() {
+            Some(entry) => Ok(entry),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData, "empty archive"))
+        }
+    } else {
+        // DATs list the tracks of disc images, not the descriptors that reference them
+        let data = scan::data_file(path);
+        Ok((scan::file_name(&data), Hashes::file(&data, full)?))
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use library;
+
+/// A time since the epoch as a UTC date, like `2019-03-14 18:05`
+pub fn date(secs: u64) -> String {
+    // Civil from days, as described at http://howardhinnant.github.io/date_algorithms.html
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days / 146097;
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, secs / 3600 % 24, secs / 60 % 60)
+}
+
+/// A duration in seconds, like `3h 25m`
+pub fn duration(secs: u64) -> String {
+    if secs >= 3600 {
+        format!("{}h {}m", secs / 3600, secs / 60 % 60)
+    } else if secs >= 60 {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// How much one ROM has been played
+#[derive(Clone, Debug, Default, RustcDecodable, RustcEncodable)]
+pub struct Plays {
+    pub count: u64,
+    /// When the ROM was last launched, in seconds since the epoch
+    pub last: u64,
+    /// Total time spent playing, in seconds
+    pub time: u64,
+    /// How the emulator exited the last time, empty while it runs
+    pub status: String,
+}
+
+impl Plays {
+    /// Lines for the ROM view
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("Played: {} times, {}", self.count, duration(self.time)),
+            format!("Last played: {}", date(self.last)),
+        ];
+        if ! self.status.is_empty() {
+            lines.push(format!("Last exit: {}", self.status));
+        }
+        lines
+    }
+}
+
+/// Play history and favorites of every ROM, by system and then ROM name
+#[derive(Default, RustcDecodable, RustcEncodable)]
+pub struct History {
+    pub systems: BTreeMap<String, BTreeMap<String, Plays>>,
+    pub favorites: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl History {
+    pub fn path() -> PathBuf {
+        let mut path = library::data_dir();
+        path.push("history.json");
+        path
+    }
+
+    pub fn load() -> History {
+        library::load_json(&History::path())
+    }
+
+    pub fn save(&self) {
+        library::save_json(&History::path(), self);
+    }
+
+    pub fn get(&self, system: &str, name: &str) -> Option<&Plays> {
+        self.systems.get(system).and_then(|roms| roms.get(name))
+    }
+
+    /// Every played ROM as (system, name, plays), most recently played first
+    pub fn recent(&self) -> Vec<(&str, &str, &Plays)> {
+        let mut recent = Vec::new();
+        for (system, roms) in self.systems.iter() {
+            for (name, plays) in roms.iter() {
+                recent.push((system.as_str(), name.as_str(), plays));
+            }
+        }
+        recent.sort_by(|a, b| b.2.last.cmp(&a.2.last));
+        recent
+    }
+
+    pub fn favorite(&self, system: &str, name: &str) -> bool {
+        self.favorites.get(system).map(|names| names.contains(name)).unwrap_or(false)
+    }
+
+    /// Add or remove a favorite, saving right away
+    pub fn toggle_favorite(&mut self, system: &str, name: &str) {
+        {
+            let names = self.favorites.entry(system.to_string()).or_insert(BTreeSet::new());
+            if ! names.remove(name) {
+                names.insert(name.to_string());
+            }
+        }
+        self.save();
+    }
+
+    fn plays(&mut self, system: &str, name: &str) -> &mut Plays {
+        self.systems.entry(system.to_string()).or_insert(BTreeMap::new())
+            .entry(name.to_string()).or_insert(Plays::default())
+    }
+
+    /// Count a launch, saved right away so it is kept even if emulition does not exit cleanly
+    pub fn launched(&mut self, system: &str, name: &str, time: u64) {
+        {
+            let plays = self.plays(system, name);
+            plays.count += 1;
+            plays.last = time;
+            plays.status.clear();
+        }
+        self.save();
+    }
+
+    /// Add the time spent playing and how the emulator exited
+    pub fn exited(&mut self, system: &str, name: &str, secs: u64, status: &str) {
+        {
+            let plays = self.plays(system, name);
+            plays.time += secs;
+            plays.status = status.to_string();
+        }
+        self.save();
+    }
+}
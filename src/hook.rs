@@ -0,0 +1,43 @@
+//! Commands run before a game is launched and after its emulator exits
+
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use template::Vars;
+
+/// Run one hook and wait for it, with the placeholders of `args` expanded.
+///
+/// A hook is a program followed by its arguments, which may be conditional like launch arguments.
+/// The whole hook is skipped when the program itself is left out by its condition, which returns false.
+/// A hook still running after `timeout` seconds is killed, so a hung one cannot hold up the frontend.
+pub fn run(hook: &[String], vars: &Vars, timeout: u64) -> Result<bool, String> {
+    let program = match hook.first().and_then(|x| vars.arg(x)) {
+        Some(program) => program,
+        None => return Ok(false)
+    };
+    let args: Vec<String> = hook[1 ..].iter().filter_map(|x| vars.arg(x)).collect();
+
+    let mut command = Command::new(&program);
+    command.args(&args);
+
+    println!("hook: {:?}", command);
+    let mut child = command.spawn().map_err(|err| format!("{}: {}", program, err))?;
+    let started = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return if status.success() {
+                Ok(true)
+            } else {
+                Err(format!("{}: {}", program, status))
+            },
+            Ok(None) if started.elapsed() >= Duration::from_secs(timeout) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("{}: killed after {} seconds", program, timeout));
+            },
+            Ok(None) => thread::sleep(Duration::from_millis(50)),
+            Err(err) => return Err(format!("{}: {}", program, err))
+        }
+    }
+}
@@ -0,0 +1,450 @@
+extern crate inotify;
+
+use self::inotify::{Inotify, WatchMask};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::os::unix;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use archive;
+use dat::Dats;
+use emulator::EmulatorConfig;
+use hash;
+use library::{self, Layout};
+use ls;
+use rom::Progress;
+use rom::header;
+use scan;
+use settings::Settings;
+
+/// What the inbox needs to know about a system to file ROMs into it
+struct System {
+    roms: String,
+    layout: Layout,
+    extensions: Vec<String>,
+    compressed: bool,
+    dat_names: Vec<String>,
+    /// Loaded the first time a file has to be identified by hash
+    dats: Option<Dats>,
+}
+
+impl System {
+    fn dats(&mut self, dir: &str) -> &Dats {
+        if self.dats.is_none() {
+            self.dats = Some(Dats::load(dir, &self.dat_names));
+        }
+        self.dats.as_ref().unwrap()
+    }
+}
+
+/// Size and modification time of a file, which stop changing once it has been written
+fn stat(path: &str) -> (u64, u64) {
+    (fs::metadata(path).map(|x| x.len()).unwrap_or(0), library::mtime(path))
+}
+
+/// Move a file, copying it when the destination is on another filesystem.
+///
+/// The source of a copy is only removed if it did not change meanwhile, so a file still being
+/// written is never lost.
+fn move_file(from: &str, to: &str) -> io::Result<()> {
+    if fs::rename(from, to).is_err() {
+        let before = stat(from);
+        fs::copy(from, to)?;
+        if stat(from) != before {
+            let _ = fs::remove_file(to);
+            return Err(io::Error::new(io::ErrorKind::Other, "still being written"));
+        }
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// A name without its disc tag, like "Game (USA) (Disc 1)" to "Game (USA)"
+fn without_disc(name: &str) -> String {
+    let lower = name.to_lowercase();
+    match lower.find("(disc ") {
+        Some(start) => match name[start ..].find(')') {
+            Some(end) => {
+                let rest = &name[start + end + 1 ..];
+                format!("{}{}", name[.. start].trim_end(), rest).trim().to_string()
+            },
+            None => name.to_string()
+        },
+        None => name.to_string()
+    }
+}
+
+/// Split inbox files into the sets that go into one game folder.
+///
+/// A descriptor goes with the tracks it references, a playlist with its discs, and the descriptors
+/// of one game's discs go together. Sets whose tracks are not all in the inbox yet are left out.
+fn groups(paths: &[String]) -> Vec<Vec<String>> {
+    let find = |name: &str| paths.iter().find(|path| scan::file_name(path).to_lowercase() == scan::file_name(name).to_lowercase()).cloned();
+
+    let mut descriptors: Vec<&String> = paths.iter().filter(|path| scan::is_descriptor(path)).collect();
+    // Playlists first, so they take their discs before the discs are grouped on their own
+    descriptors.sort_by_key(|path| scan::extension(path) != "m3u");
+
+    let mut taken: Vec<String> = Vec::new();
+    let mut groups: Vec<Vec<String>> = Vec::new();
+    let mut waiting: Vec<usize> = Vec::new();
+    for descriptor in descriptors {
+        if taken.contains(descriptor) {
+            continue;
+        }
+
+        let mut group = vec![descriptor.clone()];
+        let mut complete = true;
+        let mut i = 0;
+        while i < group.len() {
+            if scan::is_descriptor(&group[i]) {
+                for track in scan::tracks(&group[i]) {
+                    match find(&track) {
+                        Some(ref path) if group.contains(path) => (),
+                        Some(path) => group.push(path),
+                        None => complete = false
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        // Discs of one game, each with its own descriptor, share a name apart from the disc tag
+        let key = without_disc(&scan::stem(descriptor));
+        let disc_of = if scan::extension(descriptor) != "m3u" && key != scan::stem(descriptor) {
+            groups.iter().position(|other| {
+                scan::extension(&other[0]) != "m3u" && scan::stem(&other[0]) != without_disc(&scan::stem(&other[0]))
+                    && without_disc(&scan::stem(&other[0])) == key
+            })
+        } else {
+            None
+        };
+
+        taken.extend(group.iter().cloned());
+        let index = match disc_of {
+            Some(index) => {
+                groups[index].extend(group);
+                index
+            },
+            None => {
+                groups.push(group);
+                groups.len() - 1
+            }
+        };
+        if ! complete {
+            waiting.push(index);
+        }
+    }
+
+    for path in paths.iter() {
+        if ! taken.contains(path) {
+            groups.push(vec![path.clone()]);
+        }
+    }
+
+    groups.into_iter().enumerate()
+        .filter(|&(i, _)| ! waiting.contains(&i))
+        .map(|(_, group)| group)
+        .collect()
+}
+
+/// Work out which system a file belongs to and the name of its game folder
+fn identify(path: &str, systems: &mut BTreeMap<String, System>, dats_dir: &str) -> Result<(String, String), String> {
+    let (inner, mut hashes) = hash::rom(path, false).map_err(|err| format!("{}", err))?;
+
+    let mut found = None;
+
+    // The header is the most reliable, but cannot be read inside an archive
+    if ! archive::is_archive(path) {
+        if let Some(header) = header::read(path) {
+            if systems.contains_key(&header.system) {
+                found = Some(header.system);
+            }
+        }
+    }
+
+    if found.is_none() {
+        // Descriptors are hashed through their first track, but tell more about the system
+        let ext = if archive::is_archive(path) { scan::extension(&inner) } else { scan::extension(path) };
+        let matching: Vec<&String> = systems.iter()
+            .filter(|&(_, system)| system.extensions.contains(&ext))
+            .map(|(key, _)| key)
+            .collect();
+        if matching.len() == 1 {
+            found = Some(matching[0].clone());
+        }
+    }
+
+    // DAT names are used for the folder when the system's DATs know the file
+    let mut name = None;
+    for (key, system) in systems.iter_mut() {
+        if found.is_some() && found.as_ref() != Some(key) {
+            continue;
+        }
+        let dats = system.dats(dats_dir);
+        if dats.wants_full(&hashes) {
+            if let Ok((_, full)) = hash::rom(path, true) {
+                hashes = full;
+            }
+        }
+        if let Some(rom) = dats.find(&hashes) {
+            found = Some(key.clone());
+            name = Some(rom.game.clone());
+            break;
+        }
+    }
+
+    match found {
+        Some(system) => Ok((system, name.unwrap_or(scan::stem(path)))),
+        None => Err(format!("no system matches {}", inner))
+    }
+}
+
+/// Milliseconds a file found without a write event must stay unchanged before it is filed
+const SETTLE: u64 = 10000;
+
+/// Archives extracted in symlink mode, which stay in the inbox, by path and modification time
+#[derive(Default, RustcEncodable, RustcDecodable)]
+struct Extracted {
+    archives: BTreeMap<String, u64>,
+}
+
+impl Extracted {
+    fn path() -> PathBuf {
+        let mut path = library::data_dir();
+        path.push("inbox.json");
+        path
+    }
+
+    fn load() -> Extracted {
+        library::load_json(&Extracted::path())
+    }
+
+    fn save(&self) {
+        library::save_json(&Extracted::path(), self);
+    }
+}
+
+struct Inbox {
+    dir: String,
+    dats: String,
+    /// Link files into the library instead of moving them
+    symlink: bool,
+    systems: BTreeMap<String, System>,
+    /// Files already handled, by modification time, so each is only tried once
+    seen: BTreeMap<String, u64>,
+    extracted: Extracted,
+    /// Files that were closed after writing or moved in, and so are whole
+    ready: BTreeSet<String>,
+    /// Files found without such an event, like those left from before a restart, by size and modification time
+    settling: BTreeMap<String, (u64, u64)>,
+}
+
+impl Inbox {
+    /// Move or link a set of files from `groups` into `<roms>/<name>/`, or `<roms>/` for flat layouts.
+    ///
+    /// The first file names the folder, without its disc tag when the set holds several discs.
+    fn file(&mut self, group: &[String]) -> Result<String, String> {
+        let symlink = self.symlink;
+        let path = &group[0];
+        let (key, mut name) = identify(path, &mut self.systems, &self.dats)?;
+        if group.iter().filter(|file| scan::is_descriptor(file)).count() > 1 {
+            name = without_disc(&name);
+        }
+        let system = &self.systems[&key];
+
+        // The artwork downloader saves into the game folder or the artwork folder, so it must exist
+        let dir = match system.layout {
+            Layout::PerGameDir => format!("{}/{}", system.roms.trim_end_matches('/'), name),
+            Layout::Flat(ref artwork) => {
+                fs::create_dir_all(artwork).map_err(|err| format!("{}: {}", artwork, err))?;
+                system.roms.clone()
+            }
+        };
+        fs::create_dir_all(&dir).map_err(|err| format!("{}: {}", dir, err))?;
+
+        // Systems that do not launch from archives get the archive's contents instead
+        if group.len() == 1 && archive::is_archive(path) && ! system.compressed {
+            let progress = Arc::new(Mutex::new(Progress::Connecting));
+            archive::extract(path, Path::new(&dir), &progress).map_err(|err| format!("extract: {}", err))?;
+            if symlink {
+                self.extracted.archives.insert(path.clone(), library::mtime(path));
+                self.extracted.save();
+            } else {
+                fs::remove_file(path).map_err(|err| format!("{}: {}", path, err))?;
+            }
+            return Ok(dir);
+        }
+
+        // Nothing is filed unless all of it can be, so descriptors never lose their tracks
+        let dests: Vec<String> = group.iter().map(|file| format!("{}/{}", dir, scan::file_name(file))).collect();
+        if let Some(dest) = dests.iter().find(|dest| Path::new(dest).exists()) {
+            return Err(format!("{} already exists", dest));
+        }
+
+        for (file, dest) in group.iter().zip(dests.iter()) {
+            if symlink {
+                let absolute = fs::canonicalize(file).map_err(|err| format!("{}", err))?;
+                unix::fs::symlink(&absolute, dest).map_err(|err| format!("link {}: {}", dest, err))?;
+            } else {
+                move_file(file, dest).map_err(|err| format!("move {}: {}", dest, err))?;
+            }
+        }
+
+        Ok(dests[0].clone())
+    }
+
+    /// True if a symlink in the library already points at this inbox file
+    fn linked(&self, path: &str) -> bool {
+        let absolute = match fs::canonicalize(path) {
+            Ok(absolute) => absolute,
+            Err(_) => return false
+        };
+
+        self.systems.values().any(|system| {
+            // Flat layouts link straight into the ROM directory
+            let mut dirs = ls(&system.roms);
+            dirs.push(system.roms.clone());
+            dirs.iter().any(|dir| {
+                let link = Path::new(dir).join(scan::file_name(path));
+                fs::read_link(&link).map(|target| target == absolute).unwrap_or(false)
+            })
+        })
+    }
+
+    /// True if a file in the inbox has nothing left to do
+    fn handled(&self, path: &str) -> bool {
+        let mtime = library::mtime(path);
+        self.seen.get(path) == Some(&mtime)
+            || (self.symlink && (self.extracted.archives.get(path) == Some(&mtime) || self.linked(path)))
+    }
+
+    /// Mark files ready once their size and modification time have stopped changing,
+    /// for those that never get a write event
+    fn settle(&mut self) {
+        let now = library::now() * 1000;
+        let mut settling = BTreeMap::new();
+        for path in ls(&self.dir) {
+            let name = scan::file_name(&path);
+            if name.starts_with('.') || ! Path::new(&path).is_file() || self.ready.contains(&path) || self.handled(&path) {
+                continue;
+            }
+
+            let stat = stat(&path);
+            if self.settling.get(&path) == Some(&stat) && now.saturating_sub(stat.1) >= SETTLE {
+                self.ready.insert(path);
+            } else {
+                settling.insert(path, stat);
+            }
+        }
+        self.settling = settling;
+    }
+
+    /// File the ready files, in the sets of `groups`
+    fn sort(&mut self) {
+        let ready: Vec<String> = self.ready.iter().cloned().collect();
+        let mut paths = Vec::new();
+        for path in ready {
+            if ! Path::new(&path).is_file() || self.handled(&path) {
+                self.ready.remove(&path);
+            } else {
+                paths.push(path);
+            }
+        }
+
+        // Sets still waiting for some of their files are left for a later pass
+        for group in groups(&paths) {
+            for path in group.iter() {
+                let mtime = library::mtime(path);
+                self.seen.insert(path.clone(), mtime);
+                self.ready.remove(path);
+            }
+
+            let name = scan::file_name(&group[0]);
+            match self.file(&group) {
+                Ok(dest) => println!("inbox: filed {} as {}", name, dest),
+                Err(err) => println!("inbox: left {}: {}", name, err)
+            }
+        }
+    }
+}
+
+/// Files everything in the inbox directory on a worker thread, then keeps watching it.
+///
+/// The library scanner notices the new game folders on its own.
+pub fn spawn(settings: &Settings, configs: &BTreeMap<String, EmulatorConfig>) {
+    let dir = match settings.inbox {
+        Some(ref dir) => dir.clone(),
+        None => return
+    };
+
+    let mut systems = BTreeMap::new();
+    for (key, config) in configs.iter() {
+        systems.insert(key.clone(), System {
+            roms: config.roms.clone(),
+            layout: config.layout(),
+            extensions: config.rom_extensions(),
+            compressed: config.compressed(),
+            dat_names: config.dats().to_vec(),
+            dats: None,
+        });
+    }
+
+    let mut inbox = Inbox {
+        dir: dir,
+        dats: settings.dats().to_string(),
+        symlink: settings.inbox_symlink(),
+        systems: systems,
+        seen: BTreeMap::new(),
+        extracted: Extracted::load(),
+        ready: BTreeSet::new(),
+        settling: BTreeMap::new(),
+    };
+
+    thread::spawn(move || {
+        if let Err(err) = fs::create_dir_all(&inbox.dir) {
+            println!("inbox {}: {}", inbox.dir, err);
+            return;
+        }
+
+        let mut inotify = match Inotify::init() {
+            Ok(inotify) => inotify,
+            Err(err) => {
+                println!("inotify: {}", err);
+                return;
+            }
+        };
+        // Files named by these events are whole, others must first stop changing
+        if let Err(err) = inotify.add_watch(&inbox.dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO) {
+            println!("watch {}: {}", inbox.dir, err);
+        }
+
+        let mut buffer = [0; 4096];
+        loop {
+            match inotify.read_events(&mut buffer) {
+                Ok(events) => for event in events {
+                    if let Some(name) = event.name.and_then(|x| x.to_str()) {
+                        if ! name.starts_with('.') {
+                            inbox.ready.insert(format!("{}/{}", inbox.dir.trim_end_matches('/'), name));
+                        }
+                    }
+                },
+                Err(err) => {
+                    println!("inotify read: {}", err);
+                    return;
+                }
+            }
+
+            inbox.settle();
+            if ! inbox.ready.is_empty() {
+                inbox.sort();
+            }
+            thread::sleep(Duration::from_millis(500));
+        }
+    });
+}
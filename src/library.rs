@@ -0,0 +1,919 @@
+extern crate inotify;
+
+use self::inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+
+use rustc_serialize::{json, Decodable, Encodable};
+
+use std::cmp::min;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use dat::Dats;
+use emulator::EmulatorConfig;
+use hash::{self, Hashes};
+use ls;
+use archive;
+use rom::{Progress, RomConfig, RomFlags};
+use rom::header::{self, Header};
+use rom::metadata::Metadata;
+use rom::overrides::Overrides;
+use rom::tags::Tags;
+use scan;
+use settings::Settings;
+
+/// Directory for emulition's own state, following the XDG base directory spec
+pub fn data_dir() -> PathBuf {
+    let mut path = match env::var("XDG_DATA_HOME") {
+        Ok(ref dir) if ! dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let mut path = PathBuf::from(env::var("HOME").unwrap_or(".".to_string()));
+            path.push(".local");
+            path.push("share");
+            path
+        }
+    };
+    path.push("emulition");
+    path
+}
+
+/// Directory for files that can be recreated, like extracted archives
+pub fn cache_dir() -> PathBuf {
+    let mut path = match env::var("XDG_CACHE_HOME") {
+        Ok(ref dir) if ! dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let mut path = PathBuf::from(env::var("HOME").unwrap_or(".".to_string()));
+            path.push(".cache");
+            path
+        }
+    };
+    path.push("emulition");
+    path
+}
+
+/// Read state saved by `save_json`, or the default if there is none or it cannot be decoded
+pub fn load_json<T: Decodable + Default>(path: &Path) -> T {
+    let mut string = String::new();
+    match File::open(path).and_then(|mut file| file.read_to_string(&mut string)) {
+        Ok(_) => match json::decode(&string) {
+            Ok(value) => value,
+            Err(err) => {
+                println!("{}: {}", path.display(), err);
+                T::default()
+            }
+        },
+        Err(_) => T::default()
+    }
+}
+
+/// Save state as JSON, written beside the path and renamed over it so readers never see a partial file
+pub fn save_json<T: Encodable>(path: &Path, value: &T) {
+    if let Some(parent) = path.parent() {
+        if let Err(err) = fs::create_dir_all(parent) {
+            println!("{}: {}", parent.display(), err);
+            return;
+        }
+    }
+
+    let string = format!("{}", json::as_pretty_json(value));
+    let temp = path.with_extension("json.tmp");
+    if let Err(err) = File::create(&temp).and_then(|mut file| file.write_all(string.as_bytes())).and_then(|_| fs::rename(&temp, path)) {
+        println!("{}: {}", path.display(), err);
+    }
+}
+
+/// Seconds since the epoch
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0)
+}
+
+/// Modification time in milliseconds since the epoch, or 0 if unknown
+pub fn mtime(path: &str) -> u64 {
+    fs::metadata(path)
+        .and_then(|x| x.modified())
+        .ok()
+        .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+        .map(|x| x.as_secs() * 1000 + x.subsec_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// How games are arranged in a system's ROM directory
+#[derive(Clone, Debug, PartialEq)]
+pub enum Layout {
+    /// Each game has its own folder, with `image.jpg` beside the ROM
+    PerGameDir,
+    /// ROMs sit directly in the directory, with artwork named after each file in a separate folder
+    Flat(String),
+}
+
+/// Artwork for a ROM in a flat directory, looked up by game name
+fn flat_image(artwork: &str, name: &str) -> String {
+    let stem = format!("{}/{}", artwork.trim_end_matches('/'), name);
+    for ext in ["jpg", "png"].iter() {
+        let path = format!("{}.{}", stem, ext);
+        if Path::new(&path).is_file() {
+            return path;
+        }
+    }
+    stem + ".jpg"
+}
+
+/// The game an artwork folder file belongs to, like "Game" for "Game.launch.toml"
+fn artwork_name(file: &str) -> String {
+    for suffix in [".launch.toml", ".toml", ".jpg", ".png"].iter() {
+        if file.ends_with(suffix) {
+            return file[.. file.len() - suffix.len()].to_string();
+        }
+    }
+    file.to_string()
+}
+
+/// Names of the ROMs in flat directories, which are their stems unless several files share one,
+/// like "Game.gb" and "Game.gbc", then their whole file names
+fn flat_names(paths: &[String]) -> BTreeMap<String, String> {
+    let mut stems: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for path in paths.iter() {
+        stems.entry(scan::stem(path)).or_insert(BTreeSet::new()).insert(scan::file_name(path));
+    }
+
+    paths.iter().map(|path| {
+        let stem = scan::stem(path);
+        let name = if stems[&stem].len() > 1 { scan::file_name(path) } else { stem };
+        (path.clone(), name)
+    }).collect()
+}
+
+/// Mount points listed in a mount table like `/proc/self/mounts` or `/etc/fstab`.
+///
+/// Spaces and other blanks in them are written as octal escapes, like `\040`.
+fn mount_points(table: &str) -> Vec<String> {
+    table.lines()
+        .filter(|line| ! line.trim_start().starts_with('#'))
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(|point| {
+            let mut bytes = Vec::new();
+            let raw = point.as_bytes();
+            let mut i = 0;
+            while i < raw.len() {
+                let code = if raw[i] == b'\\' && i + 4 <= raw.len() {
+                    str::from_utf8(&raw[i + 1..i + 4]).ok().and_then(|code| u8::from_str_radix(code, 8).ok())
+                } else {
+                    None
+                };
+                match code {
+                    Some(code) => {
+                        bytes.push(code);
+                        i += 4;
+                    },
+                    None => {
+                        bytes.push(raw[i]);
+                        i += 1;
+                    }
+                }
+            }
+            String::from_utf8_lossy(&bytes).trim_end_matches('/').to_string()
+        })
+        .collect()
+}
+
+/// The mount point out of `points` that holds a directory, which is the longest containing it
+fn mount_point<'a>(dir: &str, points: &'a [String]) -> Option<&'a String> {
+    points.iter()
+        .filter(|point| dir == point.as_str() || dir.starts_with(&format!("{}/", point)))
+        .max_by_key(|point| point.len())
+}
+
+/// True if a ROM directory can be read and the drive or network share it is on is mounted.
+///
+/// A directory is on a drive when it is under a mount point from `/etc/fstab`, or under
+/// `/media` or `/run/media`, where desktops mount removable drives. Otherwise it is on the
+/// root filesystem and only needs to exist.
+pub fn available(root: &str) -> bool {
+    let dir = match fs::canonicalize(root) {
+        Ok(ref dir) if fs::read_dir(dir).is_ok() => dir.to_string_lossy().into_owned(),
+        _ => return false
+    };
+
+    let mounted = mount_points(&mounts());
+    let expected = mount_points(&fs::read_to_string("/etc/fstab").unwrap_or(String::new()));
+    if let Some(point) = mount_point(&dir, &expected) {
+        if ! point.is_empty() && ! mounted.contains(point) {
+            return false;
+        }
+    }
+
+    if dir.starts_with("/media/") || dir.starts_with("/run/media/") {
+        return match mount_point(&dir, &mounted) {
+            Some(point) => point.starts_with("/media/") || point.starts_with("/run/media/"),
+            None => false
+        };
+    }
+
+    true
+}
+
+#[derive(Clone, Debug, Default, RustcDecodable, RustcEncodable)]
+pub struct Entry {
+    pub name: String,
+    /// The ROM directory this game was found in
+    pub root: String,
+    /// Game folder, empty for ROMs in a flat directory
+    pub dir: String,
+    pub dir_mtime: u64,
+    pub file: String,
+    /// Every disc of a multi-disc game, starting with `file`, None for games on one disc
+    pub discs: Option<Vec<String>>,
+    pub size: u64,
+    pub mtime: u64,
+    /// When the game was first found, in seconds since the epoch
+    pub added: u64,
+    /// The file that was hashed, which differs from `file` for archives and descriptors
+    pub hashed: String,
+    pub hashes: Hashes,
+    /// Name of the game in the DAT that identified this ROM
+    pub canonical: String,
+    pub flags: Vec<RomFlags>,
+    pub header: Option<Header>,
+    /// Name of the DAT that identified this ROM
+    pub dat: String,
+    pub image: String,
+    /// Path of the `metadata.toml` sidecar, which need not exist
+    pub sidecar: String,
+    pub sidecar_mtime: u64,
+    pub metadata: Metadata,
+    /// Launch profile the game is pinned to, by name
+    pub profile: Option<String>,
+}
+
+impl Entry {
+    fn new(name: &str, root: &str, dir: &str, extensions: &[String], dats: &Dats) -> Entry {
+        let mut entry = Entry {
+            name: name.to_string(),
+            root: root.to_string(),
+            dir: dir.to_string(),
+            dir_mtime: mtime(dir),
+            added: now(),
+            image: dir.to_string() + "/image.jpg",
+            sidecar: dir.to_string() + "/metadata.toml",
+            ..Entry::default()
+        };
+
+        let discs = scan::find_discs(dir, extensions);
+        if let Some(file) = discs.first() {
+            entry.read(file.clone());
+        }
+        if discs.len() > 1 {
+            entry.discs = Some(discs);
+        }
+        entry.load_metadata();
+
+        entry.identify(dats);
+        entry
+    }
+
+    /// Entry for a ROM in a flat directory, with artwork and sidecars named after the game
+    fn flat(name: &str, root: &str, file: &str, artwork: &str, dats: &Dats) -> Entry {
+        let mut entry = Entry {
+            name: name.to_string(),
+            root: root.to_string(),
+            added: now(),
+            image: flat_image(artwork, name),
+            sidecar: format!("{}/{}.toml", artwork.trim_end_matches('/'), name),
+            ..Entry::default()
+        };
+
+        entry.read(file.to_string());
+        entry.load_metadata();
+
+        entry.identify(dats);
+        entry
+    }
+
+    /// Stat, hash and parse the header of the ROM file, with only the CRC32 to begin with
+    fn read(&mut self, file: String) {
+        self.size = fs::metadata(&file).map(|x| x.len()).unwrap_or(0);
+        self.mtime = mtime(&file);
+        match hash::rom(&file, false) {
+            Ok((hashed, hashes)) => {
+                self.hashed = hashed;
+                self.hashes = hashes;
+            },
+            Err(err) => println!("{}: {}", file, err)
+        }
+        if ! archive::is_archive(&file) {
+            self.header = header::read(&file);
+        }
+        self.file = file;
+    }
+
+    /// Path of the `launch.toml` sidecar, next to the metadata one
+    fn overrides(&self) -> String {
+        if self.dir.is_empty() {
+            format!("{}.launch.toml", self.sidecar.trim_end_matches(".toml"))
+        } else {
+            format!("{}/launch.toml", self.dir)
+        }
+    }
+
+    /// Read the sidecar again if it changed, returning true if it did
+    pub fn load_metadata(&mut self) -> bool {
+        let sidecar_mtime = mtime(&self.sidecar);
+        if sidecar_mtime == self.sidecar_mtime {
+            return false;
+        }
+
+        self.sidecar_mtime = sidecar_mtime;
+        self.metadata = Metadata::load(&self.sidecar);
+        true
+    }
+
+    /// Look the artwork up again for flat layouts, where it may be a jpg or a png, returning true if it changed
+    fn find_image(&mut self, layout: &Layout) -> bool {
+        match *layout {
+            Layout::Flat(ref artwork) => {
+                let image = flat_image(artwork, &self.name);
+                let changed = image != self.image;
+                self.image = image;
+                changed
+            },
+            Layout::PerGameDir => false
+        }
+    }
+
+    /// Look the ROM up in the DAT files, computing the MD5 and SHA1 only for ROMs whose CRC32 matches
+    fn identify(&mut self, dats: &Dats) {
+        self.canonical.clear();
+        self.flags.clear();
+        self.dat.clear();
+
+        if self.hashes.size == 0 {
+            return;
+        }
+
+        if dats.wants_full(&self.hashes) {
+            match hash::rom(&self.file, true) {
+                Ok((hashed, hashes)) => {
+                    self.hashed = hashed;
+                    self.hashes = hashes;
+                },
+                Err(err) => println!("{}: {}", self.file, err)
+            }
+        }
+
+        if let Some(found) = dats.identify(&self.hashes) {
+            self.canonical = found.game;
+            self.flags = found.flags;
+            self.dat = found.dat;
+        }
+    }
+
+    /// True if neither the game directory nor its ROM file changed since indexing
+    fn fresh(&self) -> bool {
+        (self.dir.is_empty() || self.dir_mtime == mtime(&self.dir))
+        && (self.file.is_empty() || self.mtime == mtime(&self.file))
+    }
+
+    pub fn rom_config(&self) -> RomConfig {
+        let tags = Tags::parse(if self.canonical.is_empty() { &self.name } else { &self.canonical });
+
+        RomConfig {
+            name: self.name.clone(),
+            file: self.file.clone(),
+            discs: self.discs.clone().unwrap_or(Vec::new()),
+            image: self.image.clone(),
+            // The DAT knows better than the name, when it identified the ROM
+            flags: if self.flags.is_empty() { tags.flags.clone() } else { self.flags.clone() },
+            canonical: self.canonical.clone(),
+            header: self.header.clone(),
+            metadata: self.metadata.clone(),
+            overrides: Overrides::load(&self.overrides()),
+            tags: tags,
+            profile: self.profile.clone(),
+            size: self.size,
+            added: self.added,
+            unavailable: ! available(&self.root),
+        }
+    }
+}
+
+/// On-disk index of one system's ROM directories, so startup only rescans what changed
+#[derive(Default, RustcDecodable, RustcEncodable)]
+pub struct Index {
+    /// Modification time of each root directory when it was last listed
+    pub roots: BTreeMap<String, u64>,
+    pub flat: bool,
+    pub extensions: Vec<String>,
+    pub dats_mtime: u64,
+    pub roms: BTreeMap<String, Entry>,
+}
+
+impl Index {
+    pub fn path(system: &str) -> PathBuf {
+        let mut path = data_dir();
+        path.push("library");
+        path.push(format!("{}.json", system));
+        path
+    }
+
+    pub fn load(system: &str) -> Index {
+        load_json(&Index::path(system))
+    }
+
+    pub fn save(&self, system: &str) {
+        save_json(&Index::path(system), self);
+    }
+
+    /// Bring the index up to date with the ROM directories, returning true if anything changed.
+    ///
+    /// Earlier roots win when the same game is in several. Games on a root that is not available
+    /// are kept as they were indexed.
+    ///
+    /// `found` is called with the number of paths checked, the total, and each entry as it is resolved.
+    pub fn reconcile<F: FnMut(usize, usize, &Entry)>(&mut self, roots: &[String], layout: &Layout, extensions: &[String], dats: &Dats, dats_mtime: u64, mut found: F) -> bool {
+        let mut changed = false;
+
+        // New DAT files can identify ROMs that were already hashed
+        if self.dats_mtime != dats_mtime {
+            self.dats_mtime = dats_mtime;
+            for entry in self.roms.values_mut() {
+                entry.identify(dats);
+            }
+            changed = true;
+        }
+
+        // A new extension list or layout can change which file is the ROM in every game
+        let flat = *layout != Layout::PerGameDir;
+        if self.extensions != extensions || self.flat != flat {
+            self.extensions = extensions.to_vec();
+            self.flat = flat;
+            self.roots.clear();
+            self.roms.clear();
+            changed = true;
+        }
+
+        // Games are only added or removed when a root directory itself changes
+        let mut paths = Vec::new();
+        let mut roots_new = BTreeMap::new();
+        for root in roots.iter() {
+            let indexed: Vec<String> = self.roms.values()
+                .filter(|entry| entry.root == *root)
+                .map(|entry| if flat { entry.file.clone() } else { entry.dir.clone() })
+                .collect();
+
+            // A root that went away is listed again in full when it comes back,
+            // since a later root may have taken over some of its games meanwhile
+            if ! available(root) {
+                paths.extend(indexed.into_iter().map(|path| (root.clone(), path, false)));
+                continue;
+            }
+
+            let root_mtime = mtime(root);
+            let listed = if self.roots.get(root) != Some(&root_mtime) {
+                changed = true;
+                match *layout {
+                    Layout::PerGameDir => ls(root).into_iter().filter(|path| Path::new(path).is_dir()).collect(),
+                    Layout::Flat(_) => scan::find_roms(root, extensions)
+                }
+            } else {
+                indexed
+            };
+            roots_new.insert(root.clone(), root_mtime);
+            paths.extend(listed.into_iter().map(|path| (root.clone(), path, true)));
+        }
+
+        // Games on available roots come first, so one that is also on a later root stays playable
+        // while the earlier root is unmounted
+        paths.sort_by_key(|x| ! x.2);
+
+        if self.roots.keys().ne(roots_new.keys()) {
+            changed = true;
+        }
+        self.roots = roots_new;
+
+        let names = if flat {
+            flat_names(&paths.iter().map(|x| x.1.clone()).collect::<Vec<String>>())
+        } else {
+            BTreeMap::new()
+        };
+
+        let total = paths.len();
+        let mut roms_new = BTreeMap::new();
+        for (i, (root, path, root_available)) in paths.into_iter().enumerate() {
+            let name = match *layout {
+                Layout::PerGameDir => path.replace(&root, "").trim_matches('/').to_string(),
+                Layout::Flat(_) => names[&path].clone()
+            };
+            if roms_new.contains_key(&name) {
+                continue;
+            }
+
+            let old = self.roms.remove(&name);
+            let entry = match old {
+                Some(ref entry) if entry.root == root && ! root_available => entry.clone(),
+                Some(ref entry) if entry.root == root && entry.fresh() => {
+                    // Sidecars are edited in place, which does not change the game directory
+                    let mut entry = entry.clone();
+                    let image_changed = entry.find_image(layout);
+                    if entry.load_metadata() || image_changed {
+                        changed = true;
+                    }
+                    entry
+                },
+                _ if ! root_available => continue,
+                _ => {
+                    changed = true;
+                    let mut entry = match *layout {
+                        Layout::PerGameDir => Entry::new(&name, &root, &path, extensions, dats),
+                        Layout::Flat(ref artwork) => Entry::flat(&name, &root, &path, artwork, dats)
+                    };
+                    if let Some(ref old) = old {
+                        entry.added = old.added;
+                        entry.profile = old.profile.clone();
+                    }
+                    entry
+                }
+            };
+            found(i + 1, total, &entry);
+            roms_new.insert(name, entry);
+        }
+
+        if ! self.roms.is_empty() {
+            changed = true;
+        }
+        self.roms = roms_new;
+
+        changed
+    }
+}
+
+/// A change to a system's ROMs, reported by `Scan`
+pub enum Change {
+    Update(RomConfig),
+    Remove(String),
+}
+
+/// Watched directories, as (root, game name) with an empty name for the root itself
+type Watches = HashMap<WatchDescriptor, (String, String)>;
+
+/// Watch a directory, which is harmless to repeat since the same directory keeps the same descriptor.
+///
+/// Repeating it matters for roots, where a drive mounted over the directory is a new one.
+fn watch(inotify: &mut Inotify, watches: &mut Watches, dir: &str, root: &str, name: &str) {
+    let mask = WatchMask::CREATE | WatchMask::CLOSE_WRITE | WatchMask::DELETE | WatchMask::MOVED_FROM | WatchMask::MOVED_TO;
+    match inotify.add_watch(dir, mask) {
+        Ok(wd) => {
+            watches.insert(wd, (root.to_string(), name.to_string()));
+        },
+        Err(err) => println!("watch {}: {}", dir, err)
+    }
+}
+
+/// Watch the artwork folder of a flat layout, where images and sidecars can be added after their ROM
+fn watch_artwork(inotify: &mut Inotify, layout: &Layout) -> Option<WatchDescriptor> {
+    match *layout {
+        Layout::Flat(ref artwork) if Path::new(artwork).is_dir() => {
+            let mask = WatchMask::CLOSE_WRITE | WatchMask::DELETE | WatchMask::MOVED_FROM | WatchMask::MOVED_TO;
+            match inotify.add_watch(artwork, mask) {
+                Ok(wd) => Some(wd),
+                Err(err) => {
+                    println!("watch {}: {}", artwork, err);
+                    None
+                }
+            }
+        },
+        _ => None
+    }
+}
+
+/// The mount table, which changes when drives and network shares come and go
+fn mounts() -> String {
+    fs::read_to_string("/proc/self/mounts").unwrap_or(String::new())
+}
+
+/// Games to pin to a launch profile, or unpin with None, as (name, profile)
+type Pins = Arc<Mutex<Vec<(String, Option<String>)>>>;
+
+/// Save the pins made since the last call into the index, returning true if any were
+fn apply_pins(index: &mut Index, pins: &Pins) -> bool {
+    let pins: Vec<(String, Option<String>)> = match pins.lock() {
+        Ok(mut pins) => pins.drain(..).collect(),
+        Err(_) => return false
+    };
+
+    let mut changed = false;
+    for (name, profile) in pins {
+        if let Some(entry) = index.roms.get_mut(&name) {
+            entry.profile = profile;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Reconciles a system's index on a worker thread, handing out ROMs as they are found,
+/// then keeps watching the ROM directories for games being added, removed or changed
+pub struct Scan {
+    progress: Arc<Mutex<Progress>>,
+    changes: Arc<Mutex<Vec<Change>>>,
+    pins: Pins,
+}
+
+impl Scan {
+    pub fn new(system: &str, config: &EmulatorConfig, settings: &Settings) -> Scan {
+        let progress = Arc::new(Mutex::new(Progress::Connecting));
+        let progress_child = progress.clone();
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let changes_child = changes.clone();
+        let pins = Arc::new(Mutex::new(Vec::new()));
+        let pins_child = pins.clone();
+        let system_child = system.to_string();
+        let roots_child = config.roots();
+        let layout_child = config.layout();
+        let extensions_child = config.extensions();
+        let dats_child = settings.dats().to_string();
+        let dat_names_child = config.dats().to_vec();
+
+        thread::spawn(move || {
+            // Watches are added before scanning, so nothing copied in meanwhile is missed
+            let mut inotify = match Inotify::init() {
+                Ok(inotify) => Some(inotify),
+                Err(err) => {
+                    println!("inotify: {}", err);
+                    None
+                }
+            };
+            let mut watches = HashMap::new();
+            let mut artwork_wd = None;
+            if let Some(ref mut inotify) = inotify {
+                for root in roots_child.iter() {
+                    if Path::new(root).is_dir() {
+                        watch(inotify, &mut watches, root, root, "");
+                    }
+                }
+                artwork_wd = watch_artwork(inotify, &layout_child);
+            }
+            let mut mount_table = mounts();
+            let mut online: Vec<bool> = roots_child.iter().map(|root| available(root)).collect();
+
+            let dats = Dats::load(&dats_child, &dat_names_child);
+            let dats_mtime = if dats.is_empty() { 0 } else { mtime(&dats_child) };
+
+            let mut index = Index::load(&system_child);
+            let changed = index.reconcile(&roots_child, &layout_child, &extensions_child, &dats, dats_mtime, |count, total, entry| {
+                if entry.file.is_empty() {
+                    println!("{}: no ROM matching {:?}", entry.dir, extensions_child);
+                }
+
+                if let Some(ref header) = entry.header {
+                    if header.misfiled(&system_child) {
+                        println!("{}: header is for {}, not {}", entry.file, header.system, system_child);
+                    }
+                }
+
+                if let Some(ref mut inotify) = inotify {
+                    if ! entry.dir.is_empty() && available(&entry.root) {
+                        watch(inotify, &mut watches, &entry.dir, &entry.root, &entry.name);
+                    }
+                }
+
+                if let Ok(mut changes) = changes_child.lock() {
+                    changes.push(Change::Update(entry.rom_config()));
+                }
+
+                if let Ok(mut progress) = progress_child.lock() {
+                    *progress = Progress::InProgress(count as u64, total as u64);
+                }
+            });
+
+            if changed {
+                index.save(&system_child);
+            }
+
+            if let Ok(mut progress) = progress_child.lock() {
+                *progress = Progress::Complete;
+            }
+
+            let mut inotify = match inotify {
+                Some(inotify) => inotify,
+                // Without inotify, pins are all that is left to do
+                None => loop {
+                    if apply_pins(&mut index, &pins_child) {
+                        index.save(&system_child);
+                    }
+                    thread::sleep(Duration::from_millis(500));
+                }
+            };
+
+            // Where a root comes in the list, since earlier roots win when a game is in several
+            let rank = |root: &str| roots_child.iter().position(|x| x == root);
+
+            let mut buffer = [0; 4096];
+            loop {
+                // Games whose files changed, flat roots that changed, and games where only the artwork or sidecars changed
+                let mut rescan = BTreeSet::new();
+                let mut relist = false;
+                let mut images = BTreeSet::new();
+
+                if apply_pins(&mut index, &pins_child) {
+                    index.save(&system_child);
+                }
+
+                match inotify.read_events(&mut buffer) {
+                    Ok(events) => for event in events {
+                        // Watches end when their directory is deleted or its drive is unmounted,
+                        // and unmounting is handled through the mount table instead
+                        if event.mask.contains(EventMask::IGNORED) {
+                            watches.remove(&event.wd);
+                            if artwork_wd.as_ref() == Some(&event.wd) {
+                                artwork_wd = None;
+                            }
+                            continue;
+                        }
+                        if event.mask.contains(EventMask::UNMOUNT) {
+                            continue;
+                        }
+
+                        let file = event.name.and_then(|x| x.to_str()).unwrap_or("").to_string();
+                        if artwork_wd.as_ref() == Some(&event.wd) {
+                            images.insert(artwork_name(&file));
+                            continue;
+                        }
+                        match watches.get(&event.wd) {
+                            Some(&(ref root, ref name)) if name.is_empty() => if layout_child != Layout::PerGameDir {
+                                relist = true;
+                            } else if event.mask.contains(EventMask::ISDIR) && ! file.is_empty() {
+                                rescan.insert((root.clone(), file));
+                            },
+                            Some(&(ref root, ref name)) => if file == "image.jpg" || file == "metadata.toml" || file == "launch.toml" {
+                                images.insert(name.clone());
+                            } else if scan::accepts(&file, &extensions_child) {
+                                rescan.insert((root.clone(), name.clone()));
+                            },
+                            None => ()
+                        }
+                    },
+                    Err(err) => {
+                        println!("inotify read: {}", err);
+                        return;
+                    }
+                }
+
+                let mount_table_new = mounts();
+                let mut remount = mount_table_new != mount_table;
+                mount_table = mount_table_new;
+
+                // Roots can also come and go without a mount, like a directory being created or removed
+                let online_new: Vec<bool> = roots_child.iter().map(|root| available(root)).collect();
+                remount = remount || online_new != online;
+                online = online_new;
+
+                if rescan.is_empty() && ! relist && images.is_empty() && ! remount {
+                    thread::sleep(Duration::from_millis(500));
+                    continue;
+                }
+
+                let mut changes = Vec::new();
+                let mut index_changed = false;
+
+                // Every root is listed again when a drive comes or goes, and for flat directories,
+                // where a file event may belong to a descriptor's track
+                if remount || relist {
+                    if remount {
+                        for root in roots_child.iter() {
+                            if Path::new(root).is_dir() {
+                                watch(&mut inotify, &mut watches, root, root, "");
+                            }
+                        }
+                    }
+                    // The artwork folder may only have been created along with the first ROM
+                    if remount || artwork_wd.is_none() {
+                        artwork_wd = watch_artwork(&mut inotify, &layout_child);
+                    }
+
+                    let before = index.roms.clone();
+                    index.reconcile(&roots_child, &layout_child, &extensions_child, &dats, dats_mtime, |_, _, entry| {
+                        if ! entry.dir.is_empty() && available(&entry.root) {
+                            watch(&mut inotify, &mut watches, &entry.dir, &entry.root, &entry.name);
+                        }
+
+                        let same = match before.get(&entry.name) {
+                            Some(old) => old.root == entry.root && old.file == entry.file && old.mtime == entry.mtime,
+                            None => false
+                        };
+                        if remount || ! same {
+                            changes.push(Change::Update(entry.rom_config()));
+                        }
+                    });
+
+                    for name in before.keys() {
+                        if ! index.roms.contains_key(name) {
+                            changes.push(Change::Remove(name.clone()));
+                        }
+                    }
+
+                    index.save(&system_child);
+                }
+
+                for &(ref root, ref name) in rescan.iter() {
+                    let dir = format!("{}/{}", root.trim_end_matches('/'), name);
+                    let owner = index.roms.get(name).map(|entry| entry.root.clone());
+                    if Path::new(&dir).is_dir() {
+                        if let Some(ref owner) = owner {
+                            if owner != root && rank(owner) < rank(root) {
+                                continue;
+                            }
+                        }
+
+                        watch(&mut inotify, &mut watches, &dir, root, name);
+
+                        let mut entry = Entry::new(name, root, &dir, &extensions_child, &dats);
+                        if let Some(old) = index.roms.get(name) {
+                            entry.added = old.added;
+                            entry.profile = old.profile.clone();
+                        }
+                        changes.push(Change::Update(entry.rom_config()));
+                        index.roms.insert(name.clone(), entry);
+                    } else if owner.as_ref() == Some(root) {
+                        index.roms.remove(name);
+                        changes.push(Change::Remove(name.clone()));
+                    }
+                }
+
+                for name in images.iter() {
+                    if ! rescan.iter().any(|x| x.1 == *name) {
+                        if let Some(entry) = index.roms.get_mut(name) {
+                            let image_changed = entry.find_image(&layout_child);
+                            if entry.load_metadata() || image_changed {
+                                index_changed = true;
+                            }
+                            changes.push(Change::Update(entry.rom_config()));
+                        }
+                    }
+                }
+
+                for &(ref root, _) in rescan.iter() {
+                    index.roots.insert(root.clone(), mtime(root));
+                    index_changed = true;
+                }
+
+                if index_changed {
+                    index.save(&system_child);
+                }
+
+                if let Ok(mut changes_lock) = changes_child.lock() {
+                    changes_lock.extend(changes);
+                }
+            }
+        });
+
+        Scan {
+            progress: progress,
+            changes: changes,
+            pins: pins,
+        }
+    }
+
+    /// Pin a game to a launch profile, or unpin it with None, which the worker saves in the index
+    pub fn pin(&self, name: &str, profile: Option<String>) {
+        if let Ok(mut pins) = self.pins.lock() {
+            pins.push((name.to_string(), profile));
+        }
+    }
+
+    pub fn progress(&self) -> Progress {
+        match self.progress.lock() {
+            Ok(progress) => progress.clone(),
+            Err(err) => Progress::Error(format!("{}", err))
+        }
+    }
+
+    /// True until the initial scan of the ROM directory is complete
+    pub fn scanning(&self) -> bool {
+        match self.progress() {
+            Progress::Complete => false,
+            _ => true
+        }
+    }
+
+    /// Take up to `max` of the changes found since the last call
+    pub fn take(&self, max: usize) -> Vec<Change> {
+        match self.changes.lock() {
+            Ok(mut changes) => {
+                let count = min(max, changes.len());
+                changes.drain(.. count).collect()
+            },
+            Err(_) => Vec::new()
+        }
+    }
+
+    /// Number of changes found but not yet taken
+    pub fn pending(&self) -> usize {
+        self.changes.lock().map(|changes| changes.len()).unwrap_or(0)
+    }
+}
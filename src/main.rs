@@ -9,13 +9,17 @@ extern crate toml;
 
 use std::cmp::min;
 use std::collections::BTreeMap;
+use std::env;
 use std::fs::{self, File};
 use std::io::Read;
 use std::path::PathBuf;
+use std::process;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
+use rustc_serialize::Decodable;
+
 use sdl2::controller::{Axis, Button};
 use sdl2::event::Event;
 use sdl2::keyboard::Scancode;
@@ -23,17 +27,41 @@ use sdl2::mouse::Mouse;
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 
+use audit::Audit;
+use collection::{Collection, Picks};
 use cursor::Cursor;
-use emulator::{Emulator, EmulatorConfig};
+use emulator::{Emulator, EmulatorConfig, Profile};
 use font::Font;
+use history::History;
+use notice::Notices;
 use rom::{Progress, Rom};
-use texture::NormalTexture;
-
+use search::Search;
+use settings::Settings;
+use template::Vars;
+use texture::{CenteredTexture, NormalTexture};
+
+pub mod archive;
+pub mod audit;
+pub mod browse;
+pub mod collection;
 pub mod cursor;
+pub mod dat;
 pub mod doperoms;
 pub mod emulator;
 pub mod font;
+pub mod group;
+pub mod hash;
+pub mod history;
+pub mod hook;
+pub mod inbox;
+pub mod library;
+pub mod notice;
+pub mod output;
 pub mod rom;
+pub mod scan;
+pub mod search;
+pub mod settings;
+pub mod template;
 pub mod texture;
 
 pub fn ls(path: &str) -> Vec<String> {
@@ -54,16 +82,243 @@ pub fn ls(path: &str) -> Vec<String> {
     entries
 }
 
+/// The game being played, as (name, file), shared with the threads that wait on emulators
+pub type Playing = Arc<Mutex<Option<(String, String)>>>;
+
 #[derive(Clone, PartialEq)]
 enum View {
-    Rom(String, usize),
+    /// A ROM of a system by name, which stays put as other ROMs come and go, and the collection it was opened from, if any
+    Rom(String, String, Option<Collection>),
     Emulator(String, bool),
+    Collection(Collection),
+    Audit(String),
     Overview
 }
 
+/// Decode one table of config.toml, printing why when it does not fit
+fn decode<T: Decodable>(key: &str, value: toml::Value) -> Option<T> {
+    match T::decode(&mut toml::Decoder::new(value)) {
+        Ok(decoded) => Some(decoded),
+        Err(err) => {
+            println!("config.toml [{}]: {}", key, err);
+            None
+        }
+    }
+}
+
+/// Read config.toml, returning the `[emulition]` settings and every system
+fn load_config() -> (Settings, BTreeMap<String, EmulatorConfig>) {
+    let mut settings = Settings::default();
+    let mut configs = BTreeMap::new();
+
+    if let Ok(mut file) = File::open("config.toml") {
+        let mut toml = String::new();
+        if let Ok(_) = file.read_to_string(&mut toml) {
+            let mut parser = toml::Parser::new(&toml);
+            match parser.parse() {
+                Some(mut parsed) => {
+                    if let Some(value) = parsed.remove("emulition") {
+                        if let Some(decoded) = decode::<Settings>("emulition", value) {
+                            settings = decoded;
+                        }
+                    }
+
+                    for (key, value) in parsed {
+                        if let Some(config) = decode::<EmulatorConfig>(&key, value) {
+                            configs.insert(key, config);
+                        }
+                    }
+                },
+                None => for error in parser.errors.iter() {
+                    println!("config.toml: {}", error);
+                }
+            }
+        }
+    }
+
+    (settings, configs)
+}
+
+/// Print audit reports for the given systems, or all of them, as text or CSV, exiting with an error if any failed
+fn print_audit(args: &[String]) {
+    let csv = args.iter().any(|arg| arg == "--csv");
+    let systems: Vec<&String> = args.iter().filter(|arg| ! arg.starts_with("--")).collect();
+
+    let (settings, configs) = load_config();
+
+    if csv {
+        println!("system,status,name,dat_name");
+    }
+
+    let mut failed = false;
+    for (key, config) in configs.iter() {
+        if systems.is_empty() || systems.contains(&key) {
+            match Audit::run(key, config, &settings) {
+                Ok(audit) => if csv {
+                    print!("{}", audit.csv());
+                } else {
+                    print!("{}", audit.text());
+                },
+                Err(err) => {
+                    eprintln!("{}: {}", key, err);
+                    failed = true;
+                }
+            }
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+/// Games that exit this soon after launching are reported like failures, as they likely never ran
+const QUICK_EXIT: u64 = 5;
+
+/// Start the emulator on a ROM file, unless another game is already running.
+///
+/// The pre-launch hooks, the emulator and the post-exit hooks run in turn on their own thread.
+fn spawn(emulator: &Emulator, profile: &Profile, name: &str, file: &str, settings: &Settings, playing_rom: &Playing, history: &Arc<Mutex<History>>, notices: &Arc<Mutex<Notices>>) {
+    let can_run = playing_rom.lock().unwrap().is_none();
+    if can_run {
+        // Taken now, so another game cannot start while the hooks run
+        *playing_rom.lock().unwrap() = Some((name.to_string(), file.to_string()));
+
+        let mut command = emulator.run(profile, name, file);
+        let vars = Vars::new(&emulator.key, name, file);
+        let pre_launch: Vec<Vec<String>> = settings.pre_launch().iter().chain(emulator.config.pre_launch().iter()).cloned().collect();
+        let post_exit: Vec<Vec<String>> = emulator.config.post_exit().iter().chain(settings.post_exit().iter()).cloned().collect();
+        let timeout = settings.hook_timeout();
+
+        let playing_rom_clone = playing_rom.clone();
+        let history_clone = history.clone();
+        let notices_clone = notices.clone();
+        let key = emulator.key.clone();
+        let name = name.to_string();
+        thread::spawn(move || {
+            // Run even if the emulator failed to start, to undo what the pre-launch hooks did
+            let exited = || {
+                for hook in post_exit.iter() {
+                    if let Err(err) = hook::run(hook, &vars, timeout) {
+                        notices_clone.lock().unwrap().push(format!("A post-exit hook of {} failed: {}", name, err));
+                    }
+                }
+            };
+
+            let mut ran = false;
+            for hook in pre_launch.iter() {
+                match hook::run(hook, &vars, timeout) {
+                    Ok(true) => ran = true,
+                    Ok(false) => (),
+                    Err(err) => {
+                        notices_clone.lock().unwrap().push(format!("{} was not launched, a pre-launch hook failed: {}", name, err));
+                        // Earlier hooks may have mounted or changed something that needs undoing
+                        if ran {
+                            exited();
+                        }
+                        *playing_rom_clone.lock().unwrap() = None;
+                        return;
+                    }
+                }
+            }
+
+            // What the emulator prints goes to the game's log, to show if the launch fails
+            match output::create(&key, &name) {
+                Ok(file) => match file.try_clone() {
+                    Ok(clone) => {
+                        command.stdout(clone);
+                        command.stderr(file);
+                    },
+                    Err(err) => println!("{}: log: {}", name, err)
+                },
+                Err(err) => println!("{}: log: {}", name, err)
+            }
+
+            println!("launching: {:?}", command);
+            match command.spawn() {
+                Ok(mut child) => {
+                    let started = library::now();
+                    history_clone.lock().unwrap().launched(&key, &name, started);
+
+                    let result = child.wait();
+                    println!("exited: {:?}", result);
+
+                    let success = match result {
+                        Ok(ref status) => status.success(),
+                        Err(_) => false
+                    };
+                    let status = match result {
+                        Ok(status) => format!("{}", status),
+                        Err(err) => format!("{}", err)
+                    };
+                    let secs = library::now().saturating_sub(started);
+                    history_clone.lock().unwrap().exited(&key, &name, secs, &status);
+
+                    if ! success || secs < QUICK_EXIT {
+                        let mut text = format!("{} exited after {}, {}", name, history::duration(secs), status);
+                        for line in output::tail(&key, &name) {
+                            text.push('\n');
+                            text.push_str(&line);
+                        }
+                        notices_clone.lock().unwrap().push(text);
+                    }
+                },
+                Err(err) => {
+                    let program = command.get_program().to_string_lossy().into_owned();
+                    notices_clone.lock().unwrap().push(format!("{} could not be launched: {}: {}", name, program, err));
+                }
+            }
+
+            exited();
+
+            *playing_rom_clone.lock().unwrap() = None;
+        });
+    } else {
+        println!("emulator already running: {:?}", playing_rom.lock().unwrap().as_ref().map(|x| &x.0));
+    }
+}
+
+/// Run a ROM with the profile asked for or its own, extracting it to the launch cache first if its system keeps ROMs compressed
+fn launch(emulator: &mut Emulator, index: usize, asked: Option<String>, settings: &Settings, playing_rom: &Playing, history: &Arc<Mutex<History>>, notices: &Arc<Mutex<Notices>>) {
+    if let Some(rom) = emulator.roms.get(index) {
+        if ! emulator.needs_extract(rom) {
+            // Multi-disc games run from a playlist generated for the launch
+            let file = match emulator.playlist(rom) {
+                Some(path) => scan::write_m3u(&path, &rom.config.discs).unwrap_or(rom.config.file.clone()),
+                None => rom.config.file.clone()
+            };
+            match emulator.profile(rom, asked.as_ref().map(|x| x.as_str())) {
+                Some(profile) => spawn(emulator, &profile, &rom.config.name, &file, settings, playing_rom, history, notices),
+                None => println!("{}: no program to run", emulator.config.name)
+            }
+            return;
+        }
+    }
+
+    let key = emulator.key.clone();
+    let extensions = emulator.config.rom_extensions();
+    if let Some(rom) = emulator.roms.get_mut(index) {
+        let extracting = match rom.progress() {
+            Some(Progress::Connecting) | Some(Progress::InProgress(_, _)) => true,
+            _ => false
+        };
+
+        if ! extracting {
+            rom.launch = Some(archive::Extract::cached(&key, &rom.config.name, &rom.config.file, &extensions, settings.cache_size(), playing_rom));
+            rom.launch_profile = asked;
+        }
+    }
+}
+
 fn main(){
     env_logger::init();
 
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--audit") {
+        print_audit(&args);
+        return;
+    }
+
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
     let controller_subsystem = sdl_context.game_controller().unwrap();
@@ -89,39 +344,60 @@ fn main(){
 
     let font = Font::new("res/DroidSans.ttf", 24);
 
-    let mut emulators = BTreeMap::new();
+    let (settings, configs) = load_config();
 
-    if let Ok(mut file) = File::open("config.toml") {
-        let mut toml = String::new();
-        if let Ok(_) = file.read_to_string(&mut toml) {
-            if let Some(parsed) = toml::Parser::new(&toml).parse() {
-                for (key, value) in parsed {
-                    if let Some(config) = toml::decode::<EmulatorConfig>(value) {
-                        emulators.insert(key, Emulator::new(&renderer, &font, config));
-                    }
-                }
-            }
-        }
+    inbox::spawn(&settings, &configs);
+
+    let mut emulators = BTreeMap::new();
+    for (key, config) in configs {
+        let emulator = Emulator::new(&renderer, &font, &settings, &key, config);
+        emulators.insert(key, emulator);
     }
 
     let playing_rom = Arc::new(Mutex::new(None));
+    let history = Arc::new(Mutex::new(History::load()));
+    let notices = Arc::new(Mutex::new(Notices::new()));
+    let mut picks = Picks::load();
 
     let mut event_pump = sdl_context.event_pump().unwrap();
 
+    let mut search = Search::new();
+
     let mut view = View::Overview;
     let mut offset = 0;
     'running: loop {
+        // Typing searches the Overview and a system's games, everywhere else keys keep their meaning
+        let searchable = match view {
+            View::Overview | View::Emulator(_, _) => true,
+            _ => false
+        };
+        let query = search.query.clone();
+
         let mut forward = false;
         let mut backward = false;
+        let mut favorite = false;
+        let mut next_sort = false;
+        let mut next_filter = false;
         let mut scroll = 0.0;
 
         for event in event_pump.poll_iter() {
             match event {
+                Event::KeyDown { scancode: Some(Scancode::Escape), .. } if searchable && search.active() => search.clear(),
                 Event::Quit {..} | Event::KeyDown { scancode: Some(Scancode::Escape), .. } => break 'running,
                 Event::KeyDown { scancode: Some(Scancode::Return), .. } => forward = true,
+                Event::KeyDown { scancode: Some(Scancode::Backspace), .. } if searchable && ! search.query.is_empty() => search.pop(),
                 Event::KeyDown { scancode: Some(Scancode::Backspace), .. } => backward = true,
+                Event::TextInput { ref text, .. } if searchable => search.push(text),
                 Event::ControllerButtonDown { button: Button::A, .. } => forward = true,
+                Event::ControllerButtonDown { button: Button::B, .. } if search.keyboard => search.keyboard = false,
                 Event::ControllerButtonDown { button: Button::B, .. } => backward = true,
+                Event::ControllerButtonDown { button: Button::X, .. } if searchable => search.keyboard = ! search.keyboard,
+                Event::KeyDown { scancode: Some(Scancode::F), .. } => favorite = true,
+                Event::ControllerButtonDown { button: Button::Y, .. } => favorite = true,
+                Event::KeyDown { scancode: Some(Scancode::F2), .. } => next_sort = true,
+                Event::KeyDown { scancode: Some(Scancode::F3), .. } => next_filter = true,
+                Event::ControllerButtonDown { button: Button::LeftShoulder, .. } => next_sort = true,
+                Event::ControllerButtonDown { button: Button::RightShoulder, .. } => next_filter = true,
                 Event::MouseButtonDown { mouse_btn: Mouse::Left, .. } => forward = true,
                 Event::MouseButtonDown { mouse_btn: Mouse::Right, .. } => backward = true,
                 Event::MouseMotion { x, y, .. } => cursor.set(&renderer, x as f32, y as f32),
@@ -187,58 +463,196 @@ fn main(){
         let height = renderer.output_size().unwrap().1 as i32;
         let mut s = min(width / 4, height / 3);
 
+        // The on-screen keyboard takes clicks before the view under it
+        if forward && search.click(&cursor, width, height) {
+            forward = false;
+        }
+        if search.query != query {
+            offset = 0;
+        }
+
         let mut new_view = view.clone();
         match view {
-            View::Rom(ref key, index) => {
-                if let Some(emulator) = emulators.get(key) {
+            View::Rom(ref key, ref name, ref from) => {
+                let back = match *from {
+                    Some(ref collection) => View::Collection(collection.clone()),
+                    None => View::Emulator(key.clone(), false)
+                };
+
+                if let Some(emulator) = emulators.get_mut(key) {
                     emulator.draw(&mut renderer, &font, x, y, s, s);
                     y += s;
 
+                    // Looked up every frame, since removed ROMs shift the ones after them
+                    let index = emulator.roms.iter().position(|rom| rom.config.name == *name).unwrap_or(emulator.roms.len());
+                    let mut launch_option = None;
+                    let mut pin_option = None;
+
                     if let Some(rom) = emulator.roms.get(index) {
                         x = s;
-                        y = 0;
-                        s = s * 3;
+                        y = offset;
+                        s = s * 2;
 
                         if cursor.inside(x, y, s, s) {
                             renderer.set_draw_color(highlight_color);
                             renderer.fill_rect(Rect::new(x, y, s as u32, s as u32).unwrap().unwrap());
 
+                            if forward && rom.config.unavailable {
+                                println!("{} is on a drive that is not mounted", rom.config.name);
+                            } else if forward && rom.is_broken() {
+                                println!("no ROM file found for {}", rom.config.name);
+                            } else if forward {
+                                launch_option = Some((index, None));
+                            }
+                        }
+
+                        rom.draw(&mut renderer, &font, x, y, s, s);
+
+                        y += s;
+                        let is_favorite = history.lock().unwrap().favorite(key, &rom.config.name);
+                        if cursor.inside(x, y, s, 32) {
+                            renderer.set_draw_color(highlight_color);
+                            renderer.fill_rect(Rect::new(x, y, s as u32, 32).unwrap().unwrap());
                             if forward {
-                                let can_run = playing_rom.lock().unwrap().is_none();
-                                if can_run {
-                                    let mut command = emulator.run(rom);
-
-                                    println!("launching: {:?}", command);
-                                    match command.spawn() {
-                                        Ok(mut child) => {
-                                            *playing_rom.lock().unwrap() = Some(rom.config.name.clone());
-
-                                            let playing_rom_clone = playing_rom.clone();
-                                            thread::spawn(move || {
-                                                println!("exited: {:?}", child.wait());
-                                                *playing_rom_clone.lock().unwrap() = None;
-                                            });
-                                        },
-                                        Err(err) => {
-                                            println!("error: {:?}", err);
-                                        }
+                                favorite = true;
+                            }
+                        }
+                        if favorite {
+                            history.lock().unwrap().toggle_favorite(key, &rom.config.name);
+                        }
+                        let text = if is_favorite { "Remove from favorites" } else { "Add to favorites" };
+                        let texture = NormalTexture::new(font.render(&renderer, text, Color::RGB(0, 0, 0)));
+                        texture.draw(&mut renderer, x + 8, y + 4);
+                        y += 32;
+
+                        // Collections taken out of config.toml only offer to remove the games still in them
+                        for collection in picks.names(&settings).iter() {
+                            let picked = picks.contains(collection, key, &rom.config.name);
+                            if ! picked && ! settings.collections().contains(collection) {
+                                continue;
+                            }
+                            if cursor.inside(x, y, s, 32) {
+                                renderer.set_draw_color(highlight_color);
+                                renderer.fill_rect(Rect::new(x, y, s as u32, 32).unwrap().unwrap());
+                                if forward {
+                                    picks.toggle(collection, key, &rom.config.name);
+                                }
+                            }
+                            let text = if picked {
+                                format!("Remove from {}", collection)
+                            } else {
+                                format!("Add to {}", collection)
+                            };
+                            let texture = NormalTexture::new(font.render(&renderer, &text, Color::RGB(0, 0, 0)));
+                            texture.draw(&mut renderer, x + 8, y + 4);
+                            y += 32;
+                        }
+
+                        // Systems with several profiles can run the game with any of them, or pin one to it
+                        let profiles = emulator.config.profiles();
+                        if profiles.len() > 1 {
+                            let blocked = rom.config.unavailable || rom.is_broken();
+                            for profile in profiles.iter() {
+                                if cursor.inside(x, y, s, 32) {
+                                    renderer.set_draw_color(highlight_color);
+                                    renderer.fill_rect(Rect::new(x, y, s as u32, 32).unwrap().unwrap());
+                                    if forward && ! blocked {
+                                        launch_option = Some((index, Some(profile.name.clone())));
                                     }
-                                } else {
-                                    println!("emulator already running: {:?}", *playing_rom.lock().unwrap());
+                                }
+                                let text = format!("Play with {}", profile.name);
+                                let texture = NormalTexture::new(font.render(&renderer, &text, Color::RGB(0, 0, 0)));
+                                texture.draw(&mut renderer, x + 8, y + 4);
+                                y += 32;
+                            }
+
+                            // Cycles through every profile, then back to the system default
+                            if cursor.inside(x, y, s, 32) {
+                                renderer.set_draw_color(highlight_color);
+                                renderer.fill_rect(Rect::new(x, y, s as u32, 32).unwrap().unwrap());
+                                if forward {
+                                    let next = match rom.config.profile {
+                                        Some(ref pinned) => profiles.iter().position(|x| x.name == *pinned).map(|i| i + 1).unwrap_or(profiles.len()),
+                                        None => 0
+                                    };
+                                    pin_option = Some(profiles.get(next).map(|x| x.name.clone()));
                                 }
                             }
+                            let text = match rom.config.profile {
+                                Some(ref pinned) => format!("Pinned to {}", pinned),
+                                None => "Pinned to system default".to_string()
+                            };
+                            let texture = NormalTexture::new(font.render(&renderer, &text, Color::RGB(0, 0, 0)));
+                            texture.draw(&mut renderer, x + 8, y + 4);
+                            y += 32;
                         }
 
-                        rom.draw(&mut renderer, &font, x, y, s, s);
+                        // Other versions of the game, the preferred one marked
+                        let versions = emulator.versions.of(index);
+                        for (i, &other) in versions.iter().enumerate() {
+                            if other == index {
+                                continue;
+                            }
+                            if let Some(version) = emulator.roms.get(other) {
+                                if cursor.inside(x, y, s, 32) {
+                                    renderer.set_draw_color(highlight_color);
+                                    renderer.fill_rect(Rect::new(x, y, s as u32, 32).unwrap().unwrap());
+                                    if forward {
+                                        new_view = View::Rom(key.clone(), version.config.name.clone(), from.clone());
+                                    }
+                                }
+                                let label = if i == 0 { "Preferred" } else { "Version" };
+                                let text = format!("{}: {}", label, version.config.title());
+                                let texture = NormalTexture::new(font.render(&renderer, &text, Color::RGB(0, 0, 0)));
+                                texture.draw(&mut renderer, x + 8, y + 4);
+                                y += 32;
+                            }
+                        }
 
-                        x = s;
-                        y = 0;
+                        // Details go below the ROM tile, starting with the description, which cannot be rendered blank
+                        if let Some(ref description) = rom.config.metadata.description.as_ref().filter(|x| ! x.trim().is_empty()) {
+                            let texture = NormalTexture::new(font.render_wrapped(&renderer, description, Color::RGB(0, 0, 0), (width - x - 16) as u32));
+                            texture.draw(&mut renderer, x + 8, y + 4);
+                            y += texture.height() + 8;
+                        }
+                        // The exact command a launch runs, with the game's overrides applied
+                        if let Some(profile) = emulator.profile(rom, None) {
+                            if ! rom.config.file.is_empty() {
+                                let file = emulator.playlist(rom).unwrap_or(rom.config.file.clone());
+                                let line = emulator.resolve(&profile, &rom.config.overrides, &rom.config.name, &file).line();
+                                let texture = NormalTexture::new(font.render_wrapped(&renderer, &line, Color::RGB(0, 0, 0), (width - x - 16) as u32));
+                                texture.draw(&mut renderer, x + 8, y + 4);
+                                y += texture.height() + 8;
+                            }
+                        }
+                        let mut details = rom.details(key);
+                        if let Some(plays) = history.lock().unwrap().get(key, &rom.config.name) {
+                            details.extend(plays.lines());
+                        }
+                        for detail in details {
+                            if y + 32 >= 0 && y < height {
+                                let texture = NormalTexture::new(font.render(&renderer, &detail, Color::RGB(0, 0, 0)));
+                                texture.draw(&mut renderer, x + 8, y + 4);
+                            }
+                            y += 32;
+                        }
 
                         if backward {
-                            new_view = View::Emulator(key.clone(), false);
+                            new_view = back;
                         }
                     } else {
-                        new_view = View::Emulator(key.clone(), false);
+                        new_view = back;
+                    }
+
+                    if let Some(profile) = pin_option.take() {
+                        if let Some(rom) = emulator.roms.get_mut(index) {
+                            rom.config.profile = profile.clone();
+                            emulator.scan.pin(&rom.config.name, profile);
+                        }
+                    }
+
+                    if let Some((index, profile)) = launch_option.take() {
+                        launch(emulator, index, profile, &settings, &playing_rom, &history, &notices);
                     }
                 } else {
                     new_view = View::Overview
@@ -296,11 +710,89 @@ fn main(){
                         y += 32;
                     }
 
+                    if cursor.inside(x, y, s, 32) {
+                        renderer.set_draw_color(highlight_color);
+                        renderer.fill_rect(Rect::new(x, y, s as u32, 32).unwrap().unwrap());
+                        if forward {
+                            if emulator.audit_job.is_none() {
+                                emulator.audit_job = Some(audit::Job::new(key, &emulator.config, &settings));
+                            }
+                            new_view = View::Audit(key.clone());
+                        }
+                    }
+                    let text = match emulator.audit {
+                        Some(Ok(ref audit)) => format!("Audit: {} of {}", audit.have.len(), audit.have.len() + audit.missing.len()),
+                        Some(Err(_)) => "Audit: failed".to_string(),
+                        None => "Audit".to_string()
+                    };
+                    let texture = NormalTexture::new(font.render(&renderer, &text, Color::RGB(0, 0, 0)));
+                    texture.draw(&mut renderer, x + 8, y + 4);
+                    y += 32;
+
+                    if cursor.inside(x, y, s, 32) {
+                        renderer.set_draw_color(highlight_color);
+                        renderer.fill_rect(Rect::new(x, y, s as u32, 32).unwrap().unwrap());
+                        if forward {
+                            next_sort = true;
+                        }
+                    }
+                    if next_sort {
+                        emulator.browse.next_sort(key);
+                    }
+                    let texture = NormalTexture::new(font.render(&renderer, &format!("Sort: {}", emulator.browse.sort.name()), Color::RGB(0, 0, 0)));
+                    texture.draw(&mut renderer, x + 8, y + 4);
+                    y += 32;
+
+                    if cursor.inside(x, y, s, 32) {
+                        renderer.set_draw_color(highlight_color);
+                        renderer.fill_rect(Rect::new(x, y, s as u32, 32).unwrap().unwrap());
+                        if forward {
+                            next_filter = true;
+                        }
+                    }
+                    if next_filter {
+                        emulator.browse.next_filter(key, &emulator.roms);
+                    }
+                    let texture = NormalTexture::new(font.render(&renderer, &format!("Filter: {}", emulator.browse.filter.name()), Color::RGB(0, 0, 0)));
+                    texture.draw(&mut renderer, x + 8, y + 4);
+                    y += 32;
+
+                    if cursor.inside(x, y, s, 32) {
+                        renderer.set_draw_color(highlight_color);
+                        renderer.fill_rect(Rect::new(x, y, s as u32, 32).unwrap().unwrap());
+                        if forward {
+                            emulator.browse.toggle_group(key);
+                        }
+                    }
+                    let text = if emulator.browse.grouped() { "1G1R: On" } else { "1G1R: Off" };
+                    let texture = NormalTexture::new(font.render(&renderer, text, Color::RGB(0, 0, 0)));
+                    texture.draw(&mut renderer, x + 8, y + 4);
+                    y += 32;
+
+                    if cursor.inside(x, y, s, 32) {
+                        renderer.set_draw_color(highlight_color);
+                        renderer.fill_rect(Rect::new(x, y, s as u32, 32).unwrap().unwrap());
+                        if forward {
+                            search.keyboard = ! search.keyboard;
+                        }
+                    }
+                    let texture = NormalTexture::new(font.render(&renderer, &format!("Search: {}", search.query), Color::RGB(0, 0, 0)));
+                    texture.draw(&mut renderer, x + 8, y + 4);
+                    y += 32;
+
                     x = s;
                     y = offset;
                     if downloads {
                         let mut download_option = None;
-                        for rom in emulator.downloads.iter() {
+                        let matching: Vec<usize> = (0 .. emulator.downloads.len())
+                            .filter(|&index| search.matches(&emulator.downloads[index].file))
+                            .collect();
+                        let shown = if emulator.browse.grouped() {
+                            emulator.download_versions.select(&matching).into_iter().map(|group| group[0]).collect()
+                        } else {
+                            matching
+                        };
+                        for rom in shown.into_iter().map(|index| &emulator.downloads[index]) {
 
                             if y + 32 >= 0 && y < height {
                                 if cursor.inside(x, y, width - x, 32) {
@@ -339,9 +831,11 @@ fn main(){
                         }
 
                         if let Some(config) = download_option.take() {
+                            let (name, file, image) = emulator.config.download_paths(&config.name, &config.file);
+
                             let mut exists = false;
                             for rom in emulator.roms.iter() {
-                                if rom.config.name == config.name {
+                                if rom.config.name == name {
                                     exists = true;
                                 }
                             }
@@ -350,34 +844,40 @@ fn main(){
                                 let mut rom = Rom::new(&renderer, config);
 
                                 {
-                                    let mut image_path = PathBuf::from("roms");
-                                    image_path.push(&key);
-                                    image_path.push(&rom.config.name);
-                                    image_path.push("image.jpg");
+                                    let image_path = PathBuf::from(&image);
                                     if ! image_path.is_file() {
                                         rom.image_dl = Some(doperoms::Download::new(&rom.config.image, &image_path));
                                     }
-                                    rom.config.image = format!("roms/{}/{}/image.jpg", key, rom.config.name);
+                                    rom.config.image = image;
                                 }
 
                                 {
-                                    let mut rom_path = PathBuf::from("roms");
-                                    rom_path.push(&key);
-                                    rom_path.push(&rom.config.name);
-                                    rom_path.push(&rom.config.file);
+                                    let rom_path = PathBuf::from(&file);
                                     if ! rom_path.is_file() {
                                         rom.doperoms = Some(doperoms::Download::rom(&emulator.config.doperoms, &rom.config.file, &rom_path));
                                     }
-                                    rom.config.file = format!("roms/{}/{}/{}", key, rom.config.name, rom.config.file);
+                                    rom.config.file = file;
                                 }
 
+                                rom.config.name = name;
+
                                 emulator.roms.push(rom);
                             } else {
                                 println!("already downloaded {}", config.file);
                             }
                         }
                     } else {
-                        for index in 0 .. emulator.roms.len() {
+                        let order: Vec<usize> = emulator.browse.order(key, &emulator.roms, &history.lock().unwrap()).into_iter()
+                            .filter(|&index| search.matches(emulator.roms[index].config.title()))
+                            .collect();
+                        // Each tile is the preferred version of a game, the others are reached from its ROM view
+                        let groups = if emulator.browse.grouped() {
+                            emulator.versions.select(&order)
+                        } else {
+                            order.into_iter().map(|index| vec![index]).collect()
+                        };
+                        for group in groups {
+                            let index = group[0];
                             if let Some(rom) = emulator.roms.get(index) {
                                 if y + s >= 0 && y < height {
                                     if cursor.inside(x, y, s, s) {
@@ -385,11 +885,17 @@ fn main(){
                                         renderer.fill_rect(Rect::new(x, y, s as u32, s as u32).unwrap().unwrap());
 
                                         if forward {
-                                            new_view = View::Rom(key.clone(), index);
+                                            new_view = View::Rom(key.clone(), rom.config.name.clone(), None);
                                         }
                                     }
 
                                     rom.draw(&mut renderer, &font, x, y, s, s);
+
+                                    if group.len() > 1 {
+                                        let text = format!("{} versions", group.len());
+                                        let texture = CenteredTexture::new(font.render(&renderer, &text, Color::RGB(0, 0, 0)));
+                                        texture.draw(&mut renderer, x + 8, y + s - 32, s - 16, 24);
+                                    }
                                 }
 
                                 x += s;
@@ -409,7 +915,126 @@ fn main(){
                     new_view = View::Overview
                 }
             },
+            View::Audit(ref key) => {
+                if let Some(emulator) = emulators.get(key) {
+                    emulator.draw(&mut renderer, &font, x, y, s, s);
+
+                    x = s;
+                    y = offset;
+
+                    let lines = match emulator.audit {
+                        _ if emulator.audit_job.is_some() => vec!["Auditing...".to_string()],
+                        Some(Ok(ref audit)) => audit.lines(),
+                        Some(Err(ref err)) => vec![format!("Audit failed: {}", err)],
+                        None => vec!["Auditing...".to_string()]
+                    };
+
+                    for line in lines.iter() {
+                        if y + 32 >= 0 && y < height {
+                            let texture = NormalTexture::new(font.render(&renderer, line, Color::RGB(0, 0, 0)));
+                            texture.draw(&mut renderer, x + 8, y + 4);
+                        }
+                        y += 32;
+                    }
+
+                    if backward {
+                        new_view = View::Emulator(key.clone(), false);
+                    }
+                } else {
+                    new_view = View::Overview
+                }
+            },
+            View::Collection(ref collection) => {
+                let history = history.lock().unwrap();
+
+                collection.draw(&mut renderer, &font, &emulators, &history, &picks, x, y, s, s);
+
+                x = s;
+                y = offset;
+                for (key, index) in collection.roms(&emulators, &history, &picks) {
+                    if let Some(rom) = emulators.get(&key).and_then(|emulator| emulator.roms.get(index)) {
+                        if y + s >= 0 && y < height {
+                            if cursor.inside(x, y, s, s) {
+                                renderer.set_draw_color(highlight_color);
+                                renderer.fill_rect(Rect::new(x, y, s as u32, s as u32).unwrap().unwrap());
+
+                                if forward {
+                                    new_view = View::Rom(key.clone(), rom.config.name.clone(), Some(collection.clone()));
+                                }
+                            }
+
+                            rom.draw(&mut renderer, &font, x, y, s, s);
+                        }
+
+                        x += s;
+
+                        if x + s > width {
+                            x = s;
+                            y += s;
+                        }
+                    }
+                }
+
+                if backward {
+                    new_view = View::Overview
+                }
+            },
+            View::Overview if ! search.query.is_empty() => {
+                let results = search.results(&emulators);
+
+                let text = format!("Search: {}", search.query);
+                let texture = CenteredTexture::new(font.render(&renderer, &text, Color::RGB(0, 0, 0)));
+                texture.draw(&mut renderer, x + 8, y + 4, s - 16, 24);
+                let text = format!("{} games", results.len());
+                let texture = CenteredTexture::new(font.render(&renderer, &text, Color::RGB(0, 0, 0)));
+                texture.draw(&mut renderer, x + 8, y + 4 + 32, s - 16, 24);
+
+                x = s;
+                y = offset;
+                for (key, index) in results {
+                    if let Some(rom) = emulators.get(&key).and_then(|emulator| emulator.roms.get(index)) {
+                        if y + s >= 0 && y < height {
+                            if cursor.inside(x, y, s, s) {
+                                renderer.set_draw_color(highlight_color);
+                                renderer.fill_rect(Rect::new(x, y, s as u32, s as u32).unwrap().unwrap());
+
+                                if forward {
+                                    new_view = View::Rom(key.clone(), rom.config.name.clone(), None);
+                                }
+                            }
+
+                            rom.draw(&mut renderer, &font, x, y, s, s);
+                        }
+
+                        x += s;
+
+                        if x + s > width {
+                            x = s;
+                            y += s;
+                        }
+                    }
+                }
+            },
             View::Overview => {
+                for collection in Collection::all(&settings, &picks) {
+                    if cursor.inside(x, y, s, s) {
+                        renderer.set_draw_color(highlight_color);
+                        renderer.fill_rect(Rect::new(x, y, s as u32, s as u32).unwrap().unwrap());
+
+                        if forward {
+                            new_view = View::Collection(collection.clone());
+                        }
+                    }
+
+                    collection.draw(&mut renderer, &font, &emulators, &history.lock().unwrap(), &picks, x, y, s, s);
+
+                    x += s;
+                    if x + s > width {
+                        x = 0;
+                        y += s;
+                    }
+                }
+
                 for (key, emulator) in emulators.iter() {
                     if cursor.inside(x, y, s, s) {
                         renderer.set_draw_color(highlight_color);
@@ -431,15 +1056,28 @@ fn main(){
             }
         };
 
+        search.draw(&mut renderer, &font, &cursor, width, height);
+
+        notices.lock().unwrap().draw(&mut renderer, &font, width, height);
+
         cursor.draw(&mut renderer);
 
         renderer.present();
 
         if new_view != view {
+            // A search survives visiting one of its games, but not leaving for another list
+            match (&view, &new_view) {
+                (&View::Rom(_, _, _), _) | (_, &View::Rom(_, _, _)) => search.keyboard = false,
+                _ => search.clear()
+            }
             offset = 0;
             view = new_view;
         } else {
             for (_, mut emulator) in emulators.iter_mut() {
+                for (profile, name, file) in emulator.take_launches() {
+                    spawn(emulator, &profile, &name, &file, &settings, &playing_rom, &history, &notices);
+                }
+
                 emulator.update(&renderer);
             }
 
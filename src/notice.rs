@@ -0,0 +1,60 @@
+//! Messages shown over every view for a while, such as launch errors
+
+use std::time::{Duration, Instant};
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Renderer;
+
+use font::Font;
+use texture::NormalTexture;
+
+/// How long a notice stays on screen
+const SHOWN: u64 = 8;
+
+struct Notice {
+    text: String,
+    time: Instant,
+}
+
+/// Notices from any thread, newest last
+pub struct Notices {
+    notices: Vec<Notice>,
+}
+
+impl Notices {
+    pub fn new() -> Notices {
+        Notices {
+            notices: Vec::new()
+        }
+    }
+
+    /// Show a message, printing it as well
+    pub fn push(&mut self, text: String) {
+        println!("{}", text);
+        self.notices.push(Notice {
+            text: text,
+            time: Instant::now(),
+        });
+    }
+
+    /// Draw the notices that have not expired, stacked up from the bottom of the window
+    pub fn draw(&mut self, renderer: &mut Renderer, font: &Font, width: i32, height: i32) {
+        self.notices.retain(|x| x.time.elapsed() < Duration::from_secs(SHOWN));
+
+        let mut y = height - 8;
+        for notice in self.notices.iter().rev() {
+            let texture = NormalTexture::new(font.render_wrapped(&renderer, &notice.text, Color::RGB(0, 0, 0), (width - 32) as u32));
+            let h = texture.height() + 16;
+            y -= h;
+
+            renderer.set_draw_color(Color::RGB(255, 224, 224));
+            renderer.fill_rect(Rect::new(8, y, (width - 16) as u32, h as u32).unwrap().unwrap());
+            renderer.set_draw_color(Color::RGB(192, 64, 64));
+            renderer.draw_rect(Rect::new(8, y, (width - 16) as u32, h as u32).unwrap().unwrap());
+            texture.draw(renderer, 16, y + 8);
+
+            y -= 8;
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Logs of what emulators print, one set per game
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use library;
+
+/// Logs kept per game besides the one of the last launch
+const KEEP: usize = 3;
+
+/// Lines of output shown when a launch fails
+const TAIL: usize = 5;
+
+/// Bytes read from the end of a log for its last lines, however long the log grew
+const TAIL_BYTES: u64 = 8192;
+
+/// Path of a game's log, `<name>.log` for the last launch and `<name>.<n>.log` for older ones
+pub fn path(system: &str, name: &str, n: usize) -> PathBuf {
+    let mut path = library::data_dir();
+    path.push("logs");
+    path.push(system);
+    if n == 0 {
+        path.push(format!("{}.log", name));
+    } else {
+        path.push(format!("{}.{}.log", name, n));
+    }
+    path
+}
+
+/// Start a new log for a launch, shifting the older ones and dropping the oldest
+pub fn create(system: &str, name: &str) -> io::Result<File> {
+    let latest = path(system, name, 0);
+    if let Some(parent) = latest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    for n in (0 .. KEEP).rev() {
+        let from = path(system, name, n);
+        if from.exists() {
+            fs::rename(&from, path(system, name, n + 1))?;
+        }
+    }
+
+    File::create(&latest)
+}
+
+/// The last lines of a game's latest log, skipping blank ones
+pub fn tail(system: &str, name: &str) -> Vec<String> {
+    let mut bytes = Vec::new();
+    let read = File::open(path(system, name, 0)).and_then(|mut file| {
+        let len = file.metadata()?.len();
+        let start = len.saturating_sub(TAIL_BYTES);
+        file.seek(SeekFrom::Start(start))?;
+        file.read_to_end(&mut bytes)?;
+        Ok(start)
+    });
+    let start = match read {
+        Ok(start) => start,
+        Err(_) => return Vec::new()
+    };
+
+    let text = String::from_utf8_lossy(&bytes);
+    // Reading from the middle of the log starts partway through a line
+    let skip = if start > 0 { 1 } else { 0 };
+    let mut lines: Vec<String> = text.lines()
+        .skip(skip)
+        .map(|x| x.trim_end())
+        .filter(|x| ! x.is_empty())
+        .map(|x| x.chars().take(200).collect())
+        .collect();
+
+    let skip = lines.len().saturating_sub(TAIL);
+    lines.drain(.. skip);
+    lines
+}
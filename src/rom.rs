@@ -1,5 +1,4 @@
 use std::path::Path;
-use std::process::Command;
 
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
@@ -7,11 +6,22 @@ use sdl2::render::Renderer;
 
 use sdl2_image::LoadTexture;
 
+use self::header::Header;
+use self::metadata::Metadata;
+use self::overrides::Overrides;
+use self::tags::Tags;
+
+use archive;
 use doperoms;
+use emulator::EmulatorConfig;
 use font::Font;
-use ls;
 use texture::{CenteredTexture, ScaledTexture};
 
+pub mod header;
+pub mod metadata;
+pub mod overrides;
+pub mod tags;
+
 #[derive(Clone)]
 pub enum Progress {
     Connecting,
@@ -20,7 +30,7 @@ pub enum Progress {
     Complete,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, RustcDecodable, RustcEncodable)]
 pub enum RomFlags {
     Alternate,
     Bad,
@@ -37,14 +47,57 @@ pub enum RomFlags {
 pub struct RomConfig {
     pub name: String,
     pub file: String,
+    /// Every disc of a multi-disc game, starting with `file`, empty for games on one disc
+    pub discs: Vec<String>,
     pub image: String,
     pub flags: Vec<RomFlags>,
+    /// Name from the DAT file that identified this ROM, if any
+    pub canonical: String,
+    pub header: Option<Header>,
+    pub metadata: Metadata,
+    /// Read from the `launch.toml` sidecar on the scan worker, empty if there is none
+    pub overrides: Overrides,
+    /// Parsed from the tags of the title
+    pub tags: Tags,
+    pub size: u64,
+    /// When the game was first found, in seconds since the epoch
+    pub added: u64,
+    /// Launch profile the game is pinned to, by name
+    pub profile: Option<String>,
+    /// The ROM is on a drive or share that is not mounted
+    pub unavailable: bool,
+}
+
+impl RomConfig {
+    /// Name to show on tiles
+    pub fn title(&self) -> &str {
+        if self.canonical.is_empty() {
+            &self.name
+        } else {
+            &self.canonical
+        }
+    }
+
+    /// Name for tiles, without the tags of the title
+    pub fn display_title(&self) -> &str {
+        if self.tags.title.is_empty() {
+            self.title()
+        } else {
+            &self.tags.title
+        }
+    }
 }
 
 pub struct Rom {
     image: Option<ScaledTexture>,
     pub image_dl: Option<doperoms::Download>,
     pub doperoms: Option<doperoms::Download>,
+    /// Extraction of a downloaded archive into the game directory
+    pub extract: Option<archive::Extract>,
+    /// Extraction into the launch cache, after which the ROM is run
+    pub launch: Option<archive::Extract>,
+    /// Profile asked for when the extraction into the launch cache started
+    pub launch_profile: Option<String>,
     pub config: RomConfig,
 }
 
@@ -58,14 +111,79 @@ impl Rom {
             },
             image_dl: None,
             doperoms: None,
+            extract: None,
+            launch: None,
+            launch_profile: None,
             config: config
         }
     }
 
+    /// Replace the config after the game directory changed on disk, reloading the artwork
+    pub fn reload(&mut self, renderer: &Renderer, config: RomConfig) {
+        self.image = if let Some(texture) = renderer.load_texture(&Path::new(&config.image)).ok() {
+            Some(ScaledTexture::new(texture))
+        } else {
+            None
+        };
+        self.config = config;
+    }
+
+    /// A ROM is broken if its folder has no file the emulator can open
+    pub fn is_broken(&self) -> bool {
+        self.doperoms.is_none() && self.extract.is_none() && self.config.file.is_empty()
+    }
+
+    /// Progress of the download or extraction the ROM is waiting on, if any
+    pub fn progress(&self) -> Option<Progress> {
+        if let Some(ref doperoms) = self.doperoms {
+            Some(doperoms.progress())
+        } else if let Some(ref extract) = self.extract {
+            Some(extract.progress())
+        } else if let Some(ref launch) = self.launch {
+            Some(launch.progress())
+        } else {
+            None
+        }
+    }
+
+    /// Lines of information about the ROM for the detail view
+    pub fn details(&self, system: &str) -> Vec<String> {
+        let mut details = self.config.metadata.lines();
+
+        if ! self.config.canonical.is_empty() {
+            details.push(format!("DAT name: {}", self.config.canonical));
+        }
+        details.extend(self.config.tags.lines());
+        if ! self.config.flags.is_empty() {
+            details.push(format!("Flags: {:?}", self.config.flags));
+        }
+        details.push(format!("File: {}", self.config.file));
+
+        if let Some(ref header) = self.config.header {
+            if header.misfiled(system) {
+                details.push(format!("Header is for {}, not {}", header.system, system));
+            }
+            if ! header.title.is_empty() {
+                details.push(format!("Internal title: {}", header.title));
+            }
+            if ! header.region.is_empty() {
+                details.push(format!("Region: {}", header.region));
+            }
+            if ! header.checksum.is_empty() {
+                details.push(format!("Checksum: {}", header.checksum));
+            }
+            for info in header.info.iter() {
+                details.push(info.clone());
+            }
+        }
+
+        details
+    }
+
     pub fn draw(&self, renderer: &mut Renderer, font: &Font, x: i32, y: i32, w: i32, h: i32) {
-        let text = if let Some(ref doperoms) = self.doperoms {
-            match doperoms.progress() {
-                Progress::Connecting => format!("{}: ...", self.config.name),
+        let text = if let Some(progress) = self.progress() {
+            match progress {
+                Progress::Connecting => format!("{}: ...", self.config.display_title()),
                 Progress::InProgress(downloaded, total) => {
                     if total > 0 {
                         let ratio = downloaded as f64 / total as f64;
@@ -74,27 +192,35 @@ impl Rom {
                             renderer.set_draw_color(Color::RGB(0, 255, 0));
                             renderer.fill_rect(Rect::new(x, y, pixels, 32).unwrap().unwrap());
                         }
-                        format!("{}: {:.1}%", self.config.name, ratio * 100.0)
+                        format!("{}: {:.1}%", self.config.display_title(), ratio * 100.0)
                     } else {
-                        format!("{}: ?%", self.config.name)
+                        format!("{}: ?%", self.config.display_title())
                     }
                 },
-                Progress::Error(error) => format!("{}: {}", self.config.name, error),
-                Progress::Complete => format!("{}: Complete", self.config.name)
+                Progress::Error(error) => format!("{}: {}", self.config.display_title(), error),
+                Progress::Complete => format!("{}: Complete", self.config.display_title())
             }
+        } else if self.config.unavailable {
+            format!("{}: Unavailable", self.config.display_title())
+        } else if self.is_broken() {
+            format!("{}: Broken", self.config.display_title())
         } else {
-            format!("{}", self.config.name)
+            format!("{}", self.config.display_title())
         };
 
         let texture = CenteredTexture::new(font.render(&renderer, &text, Color::RGB(0, 0, 0)));
         texture.draw(renderer, x + 8, y + 4, w - 16, 24);
 
+        self.draw_image(renderer, x + 8, y + 8 + 32, w - 16, h - 32 - 16);
+    }
+
+    pub fn draw_image(&self, renderer: &mut Renderer, x: i32, y: i32, w: i32, h: i32) {
         if let Some(ref image) = self.image {
-            image.draw(renderer, x + 8, y + 8 + 32, w - 16, h - 32 - 16);
+            image.draw(renderer, x, y, w, h);
         }
     }
 
-    pub fn update(&mut self, renderer: &Renderer){
+    pub fn update(&mut self, renderer: &Renderer, config: &EmulatorConfig){
         let take_image_dl = if let Some(ref image_dl) = self.image_dl {
             match image_dl.progress() {
                 Progress::Complete => true,
@@ -123,28 +249,25 @@ impl Rom {
 
         if take_doperoms {
             if let Some(doperoms) = self.doperoms.take() {
-                let mut dir = String::new();
-                if let Some(dir_path) = Path::new(&self.config.file).parent() {
-                    if let Some(dir_str) = dir_path.to_str() {
-                        dir = dir_str.to_string();
-                    }
+                // Compressed systems launch straight from the downloaded archive
+                if ! config.compressed() && archive::is_archive(&self.config.file) {
+                    self.extract = Some(archive::Extract::beside(&self.config.file, &config.rom_extensions()));
                 }
+            }
+        }
 
-                match Command::new("7z").arg("x").arg(&format!("-o{}", dir)).arg(&self.config.file).status() {
-                    Ok(status) => {
-                        println!("7z: {}", status);
-
-                        if status.success() {
-                            for file in ls(&dir) {
-                                if file.ends_with(".jpg") || file.ends_with(".7z") || file.ends_with(".zip") {
-                                } else {
-                                    self.config.file = file;
-                                }
-                            }
-                        }
-                    },
-                    Err(err) => println!("7z: {}", err)
-                }
+        let take_extract = if let Some(ref extract) = self.extract {
+            match extract.progress() {
+                Progress::Complete => true,
+                _ => false
+            }
+        } else {
+            false
+        };
+
+        if take_extract {
+            if let Some(extract) = self.extract.take() {
+                self.config.file = extract.result().unwrap_or(String::new());
             }
         }
     }
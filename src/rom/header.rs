@@ -0,0 +1,452 @@
+//! Parsing of the internal headers of cartridge and handheld ROMs
+
+use std::fs::File;
+use std::io::Read;
+
+/// Information from a ROM's internal header
+#[derive(Clone, Debug, Default, PartialEq, RustcDecodable, RustcEncodable)]
+pub struct Header {
+    /// System the header belongs to, named like the systems in config.toml
+    pub system: String,
+    pub title: String,
+    pub region: String,
+    pub checksum: String,
+    /// Mapper, size and format details, one per line
+    pub info: Vec<String>,
+}
+
+/// Systems that headers can identify
+pub const SYSTEMS: &'static [&'static str] = &["ds", "gba", "gbc", "genesis", "n64", "nes", "snes"];
+
+impl Header {
+    /// True if the header belongs to a different system than the one the ROM is filed under.
+    ///
+    /// Systems that headers cannot identify are never reported.
+    pub fn misfiled(&self, system: &str) -> bool {
+        SYSTEMS.contains(&system) && self.system != system
+    }
+}
+
+/// How many bytes have to be read to find any supported header, including SNES HiROM with a copier header
+const HEADER_SIZE: usize = 0x10200;
+
+fn title(bytes: &[u8]) -> String {
+    let mut title = String::new();
+    for &byte in bytes.iter() {
+        if byte == 0 {
+            break;
+        }
+        if byte >= 0x20 && byte < 0x7F {
+            title.push(byte as char);
+        }
+    }
+    title.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn u16_le(bytes: &[u8]) -> u16 {
+    bytes[0] as u16 | (bytes[1] as u16) << 8
+}
+
+fn u16_be(bytes: &[u8]) -> u16 {
+    (bytes[0] as u16) << 8 | bytes[1] as u16
+}
+
+fn size_string(size: u64) -> String {
+    if size >= 1024 * 1024 && size % (1024 * 1024) == 0 {
+        format!("{} MiB", size / (1024 * 1024))
+    } else {
+        format!("{} KiB", size / 1024)
+    }
+}
+
+/// Region from the last letter of a Nintendo game code
+fn region_code(code: u8) -> String {
+    match code {
+        b'A' => "World",
+        b'C' => "China",
+        b'D' => "Germany",
+        b'E' => "USA",
+        b'F' => "France",
+        b'H' => "Netherlands",
+        b'I' => "Italy",
+        b'J' => "Japan",
+        b'K' => "Korea",
+        b'P' => "Europe",
+        b'S' => "Spain",
+        b'U' => "Australia",
+        _ => ""
+    }.to_string()
+}
+
+fn nes(data: &[u8]) -> Option<Header> {
+    if data.len() < 16 || &data[0 .. 4] != b"NES\x1A" {
+        return None;
+    }
+
+    let nes2 = data[7] & 0x0C == 0x08;
+    let mut mapper = (data[6] >> 4) as u16 | (data[7] & 0xF0) as u16;
+    let mut prg = data[4] as u64;
+    let mut chr = data[5] as u64;
+    let region = if nes2 {
+        mapper |= ((data[8] & 0x0F) as u16) << 8;
+        prg |= ((data[9] & 0x0F) as u64) << 8;
+        chr |= ((data[9] >> 4) as u64) << 8;
+        match data[12] & 0x03 {
+            0 => "NTSC",
+            1 => "PAL",
+            2 => "Multiple",
+            _ => "Dendy"
+        }
+    } else if data[9] & 0x01 == 0x01 {
+        "PAL"
+    } else {
+        "NTSC"
+    };
+
+    let mut info = vec![
+        format!("Format: {}", if nes2 { "NES 2.0" } else { "iNES" }),
+        format!("Mapper: {}", mapper),
+    ];
+    if nes2 {
+        info.push(format!("Submapper: {}", data[8] >> 4));
+    }
+    info.push(format!("PRG ROM: {}", size_string(prg * 16 * 1024)));
+    info.push(format!("CHR ROM: {}", size_string(chr * 8 * 1024)));
+    if data[6] & 0x02 == 0x02 {
+        info.push("Battery".to_string());
+    }
+
+    Some(Header {
+        system: "nes".to_string(),
+        title: String::new(),
+        region: region.to_string(),
+        checksum: String::new(),
+        info: info,
+    })
+}
+
+fn n64(data: &[u8]) -> Option<Header> {
+    if data.len() < 0x40 {
+        return None;
+    }
+
+    // Convert byte-swapped and little-endian dumps to big-endian z64 order
+    let magic = &data[0 .. 4];
+    let (format, rom): (&str, Vec<u8>) = if magic == [0x80, 0x37, 0x12, 0x40] {
+        ("z64", data[.. 0x40].to_vec())
+    } else if magic == [0x37, 0x80, 0x40, 0x12] {
+        ("v64", data[.. 0x40].chunks(2).flat_map(|x| vec![x[1], x[0]]).collect())
+    } else if magic == [0x40, 0x12, 0x37, 0x80] {
+        ("n64", data[.. 0x40].chunks(4).flat_map(|x| vec![x[3], x[2], x[1], x[0]]).collect())
+    } else {
+        return None;
+    };
+
+    Some(Header {
+        system: "n64".to_string(),
+        title: title(&rom[0x20 .. 0x34]),
+        region: match rom[0x3E] {
+            b'E' => "USA".to_string(),
+            b'J' => "Japan".to_string(),
+            b'N' => "Canada".to_string(),
+            code => region_code(code)
+        },
+        checksum: format!("{:02X}{:02X}{:02X}{:02X} {:02X}{:02X}{:02X}{:02X}",
+            rom[0x10], rom[0x11], rom[0x12], rom[0x13],
+            rom[0x14], rom[0x15], rom[0x16], rom[0x17]),
+        info: vec![
+            format!("Format: {}", format),
+            format!("Game code: {}", title(&rom[0x3B .. 0x3F])),
+        ],
+    })
+}
+
+fn gba(data: &[u8]) -> Option<Header> {
+    // Fixed value and the start of the Nintendo logo
+    if data.len() < 0xC0 || data[0xB2] != 0x96 || &data[0x04 .. 0x08] != [0x24, 0xFF, 0xAE, 0x51] {
+        return None;
+    }
+
+    Some(Header {
+        system: "gba".to_string(),
+        title: title(&data[0xA0 .. 0xAC]),
+        region: region_code(data[0xAF]),
+        checksum: format!("{:02X}", data[0xBD]),
+        info: vec![
+            format!("Game code: {}", title(&data[0xAC .. 0xB0])),
+            format!("Maker: {}", title(&data[0xB0 .. 0xB2])),
+            format!("Version: {}", data[0xBC]),
+        ],
+    })
+}
+
+fn nds(data: &[u8]) -> Option<Header> {
+    // CRC16 of the Nintendo logo, which is the same in every cartridge
+    if data.len() < 0x200 || u16_le(&data[0x15C .. 0x15E]) != 0xCF56 {
+        return None;
+    }
+
+    Some(Header {
+        system: "ds".to_string(),
+        title: title(&data[0x00 .. 0x0C]),
+        region: region_code(data[0x0F]),
+        checksum: format!("{:04X}", u16_le(&data[0x15E .. 0x160])),
+        info: vec![
+            format!("Game code: {}", title(&data[0x0C .. 0x10])),
+            format!("Maker: {}", title(&data[0x10 .. 0x12])),
+            format!("Unit: {}", match data[0x12] {
+                0x00 => "DS",
+                0x02 => "DS and DSi",
+                0x03 => "DSi",
+                _ => "Unknown"
+            }),
+            format!("Capacity: {}", size_string((128 * 1024) << (data[0x14] as u64 & 0x0F))),
+        ],
+    })
+}
+
+fn gb(data: &[u8]) -> Option<Header> {
+    // Start of the Nintendo logo
+    if data.len() < 0x150 || &data[0x104 .. 0x108] != [0xCE, 0xED, 0x66, 0x66] {
+        return None;
+    }
+
+    let cgb = data[0x143] & 0x80 == 0x80;
+    let title_end = if cgb { 0x13F } else { 0x144 };
+
+    let mut header_checksum: u8 = 0;
+    for &byte in data[0x134 .. 0x14D].iter() {
+        header_checksum = header_checksum.wrapping_sub(byte).wrapping_sub(1);
+    }
+
+    let mut info = vec![
+        format!("Model: {}", match data[0x143] {
+            0x80 => "Game Boy Color compatible",
+            0xC0 => "Game Boy Color only",
+            _ => "Game Boy"
+        }),
+        format!("Cartridge type: {:02X}", data[0x147]),
+        format!("ROM size: {}", size_string((32 * 1024) << (data[0x148] as u64 & 0x0F))),
+    ];
+    if header_checksum != data[0x14D] {
+        info.push("Header checksum mismatch".to_string());
+    }
+
+    Some(Header {
+        system: "gbc".to_string(),
+        title: title(&data[0x134 .. title_end]),
+        region: if data[0x14A] == 0 { "Japan" } else { "World" }.to_string(),
+        checksum: format!("{:04X}", u16_be(&data[0x14E .. 0x150])),
+        info: info,
+    })
+}
+
+fn genesis(data: &[u8]) -> Option<Header> {
+    if data.len() < 0x200 || ! (&data[0x100 .. 0x104] == b"SEGA" || &data[0x101 .. 0x105] == b"SEGA") {
+        return None;
+    }
+
+    let mut region = String::new();
+    for &code in data[0x1F0 .. 0x1F3].iter() {
+        let name = match code {
+            b'J' => "Japan",
+            b'U' => "USA",
+            b'E' => "Europe",
+            _ => ""
+        };
+        if ! name.is_empty() {
+            if ! region.is_empty() {
+                region.push_str(", ");
+            }
+            region.push_str(name);
+        }
+    }
+
+    let domestic = title(&data[0x120 .. 0x150]);
+    let overseas = title(&data[0x150 .. 0x180]);
+
+    Some(Header {
+        system: "genesis".to_string(),
+        title: if overseas.is_empty() { domestic.clone() } else { overseas },
+        region: region,
+        checksum: format!("{:04X}", u16_be(&data[0x18E .. 0x190])),
+        info: vec![
+            format!("Console: {}", title(&data[0x100 .. 0x110])),
+            format!("Domestic title: {}", domestic),
+            format!("Serial: {}", title(&data[0x180 .. 0x18E])),
+        ],
+    })
+}
+
+/// Score how likely a SNES header is to be at `offset`
+fn snes_score(data: &[u8], offset: usize, hirom: bool) -> i32 {
+    if data.len() < offset + 0x40 {
+        return -1;
+    }
+
+    let header = &data[offset ..];
+    let mut score = 0;
+
+    let checksum = u16_le(&header[0x1E .. 0x20]);
+    let complement = u16_le(&header[0x1C .. 0x1E]);
+    if checksum ^ complement == 0xFFFF {
+        score += 4;
+    }
+
+    let map_mode = header[0x15] & 0x2F;
+    if (hirom && (map_mode == 0x21 || map_mode == 0x25)) || (! hirom && map_mode == 0x20) {
+        score += 2;
+    }
+
+    if header[0 .. 21].iter().all(|&x| x >= 0x20 && x < 0x7F) {
+        score += 1;
+    }
+
+    score
+}
+
+fn snes(data: &[u8], size: u64) -> Option<Header> {
+    // Copier devices prepend a 512 byte header
+    let copier = size % 1024 == 512;
+    let base = if copier { 512 } else { 0 };
+
+    let lorom = snes_score(data, base + 0x7FC0, false);
+    let hirom = snes_score(data, base + 0xFFC0, true);
+    let (offset, is_hirom, score) = if hirom > lorom {
+        (base + 0xFFC0, true, hirom)
+    } else {
+        (base + 0x7FC0, false, lorom)
+    };
+
+    // A valid checksum or map mode is required, the title alone matches too much
+    if score < 2 {
+        return None;
+    }
+
+    let header = &data[offset ..];
+    let mut info = vec![
+        format!("Mapping: {}", if header[0x15] & 0x2F == 0x25 {
+            "ExHiROM"
+        } else if is_hirom {
+            "HiROM"
+        } else {
+            "LoROM"
+        }),
+        format!("Speed: {}", if header[0x15] & 0x10 == 0x10 { "FastROM" } else { "SlowROM" }),
+        format!("ROM size: {}", size_string(1024 << (header[0x17] as u64 & 0x1F))),
+        format!("Version: 1.{}", header[0x1B]),
+    ];
+    if copier {
+        info.push("Copier header".to_string());
+    }
+
+    Some(Header {
+        system: "snes".to_string(),
+        title: title(&header[0 .. 21]),
+        region: match header[0x19] {
+            0x00 => "Japan",
+            0x01 => "USA",
+            0x02 ..= 0x0C => "Europe",
+            0x0D => "Korea",
+            _ => ""
+        }.to_string(),
+        checksum: format!("{:04X}", u16_le(&header[0x1E .. 0x20])),
+        info: info,
+    })
+}
+
+/// Parse the header from the start of a ROM, where `size` is the size of the whole file
+pub fn parse(data: &[u8], size: u64) -> Option<Header> {
+    nes(data)
+        .or_else(|| n64(data))
+        .or_else(|| nds(data))
+        .or_else(|| gba(data))
+        .or_else(|| gb(data))
+        .or_else(|| genesis(data))
+        .or_else(|| snes(data, size))
+}
+
+pub fn read(path: &str) -> Option<Header> {
+    let mut file = File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+
+    let mut data = Vec::new();
+    file.by_ref().take(HEADER_SIZE as u64).read_to_end(&mut data).ok()?;
+
+    parse(&data, size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A blank SNES ROM with a valid header at `offset`
+    fn snes_rom(size: usize, offset: usize, map_mode: u8) -> Vec<u8> {
+        let mut data = vec![0; size];
+        let header = &mut data[offset .. offset + 0x40];
+        header[.. 21].copy_from_slice(b"SUPER MARIOWORLD     ");
+        header[0x15] = map_mode;
+        header[0x17] = 0x09;
+        header[0x19] = 0x01;
+        header[0x1C .. 0x20].copy_from_slice(&[0x5F, 0x5C, 0xA0, 0xA3]);
+        data
+    }
+
+    #[test]
+    fn snes_lorom() {
+        let data = snes_rom(0x10000, 0x7FC0, 0x20);
+        let header = parse(&data, 0x80000).unwrap();
+        assert_eq!(header.system, "snes");
+        assert_eq!(header.title, "SUPER MARIOWORLD");
+        assert_eq!(header.region, "USA");
+        assert_eq!(header.checksum, "A3A0");
+        assert_eq!(header.info[0], "Mapping: LoROM");
+        assert_eq!(header.info[2], "ROM size: 512 KiB");
+    }
+
+    #[test]
+    fn snes_hirom_with_copier_header() {
+        let data = snes_rom(0x10200, 0x200 + 0xFFC0, 0x31);
+        let header = parse(&data, 0x100200).unwrap();
+        assert_eq!(header.title, "SUPER MARIOWORLD");
+        assert_eq!(header.info[0], "Mapping: HiROM");
+        assert_eq!(header.info[1], "Speed: FastROM");
+        assert!(header.info.contains(&"Copier header".to_string()));
+
+        // Without the copier header in the size, the header is looked for in the wrong place
+        assert_eq!(parse(&data, 0x100000), None);
+    }
+
+    #[test]
+    fn snes_needs_checksum_or_map_mode() {
+        let mut data = snes_rom(0x10000, 0x7FC0, 0x00);
+        data[0x7FC0 + 0x1C] = 0;
+        assert_eq!(parse(&data, 0x80000), None);
+    }
+
+    /// The start of a big-endian z64 ROM
+    fn z64() -> Vec<u8> {
+        let mut data = vec![0; 0x40];
+        data[.. 4].copy_from_slice(&[0x80, 0x37, 0x12, 0x40]);
+        data[0x10 .. 0x18].copy_from_slice(&[0x63, 0x5A, 0x2B, 0xFF, 0x8B, 0x02, 0x23, 0x26]);
+        data[0x20 .. 0x34].copy_from_slice(b"SUPER MARIO 64      ");
+        data[0x3B .. 0x3F].copy_from_slice(b"NSME");
+        data
+    }
+
+    #[test]
+    fn n64_byte_orders() {
+        let z64 = z64();
+        let v64: Vec<u8> = z64.chunks(2).flat_map(|x| vec![x[1], x[0]]).collect();
+        let n64: Vec<u8> = z64.chunks(4).flat_map(|x| vec![x[3], x[2], x[1], x[0]]).collect();
+
+        for &(data, format) in [(&z64, "z64"), (&v64, "v64"), (&n64, "n64")].iter() {
+            let header = parse(data, 8 * 1024 * 1024).unwrap();
+            assert_eq!(header.system, "n64");
+            assert_eq!(header.title, "SUPER MARIO 64");
+            assert_eq!(header.region, "USA");
+            assert_eq!(header.checksum, "635A2BFF 8B022326");
+            assert_eq!(header.info, vec![format!("Format: {}", format), "Game code: NSME".to_string()]);
+        }
+    }
+}
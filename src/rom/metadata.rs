@@ -0,0 +1,94 @@
+//! Per-game details from a `metadata.toml` sidecar
+
+use std::fs::File;
+use std::io::Read;
+
+use toml;
+
+/// Details about a game, all optional, written by hand or by a scraper
+#[derive(Clone, Debug, Default, PartialEq, RustcDecodable, RustcEncodable)]
+pub struct Metadata {
+    pub description: Option<String>,
+    /// Release date, as written in the sidecar, such as `1991-08-23` or `1991`
+    pub release: Option<String>,
+    pub developer: Option<String>,
+    pub publisher: Option<String>,
+    pub genre: Option<String>,
+    /// Player count, such as `1` or `1-4`
+    pub players: Option<String>,
+    pub region: Option<String>,
+    pub rating: Option<String>,
+}
+
+impl Metadata {
+    /// Read a sidecar, returning empty metadata if there is none
+    pub fn load(path: &str) -> Metadata {
+        let mut string = String::new();
+        if File::open(path).and_then(|mut file| file.read_to_string(&mut string)).is_err() {
+            return Metadata::default();
+        }
+
+        let mut parser = toml::Parser::new(&string);
+        match parser.parse() {
+            // Fields are read one by one, so a mistyped one does not lose the rest
+            Some(table) => Metadata {
+                description: field(&table, "description", path),
+                release: field(&table, "release", path),
+                developer: field(&table, "developer", path),
+                publisher: field(&table, "publisher", path),
+                genre: field(&table, "genre", path),
+                players: field(&table, "players", path),
+                region: field(&table, "region", path),
+                rating: field(&table, "rating", path),
+            },
+            None => {
+                for error in parser.errors.iter() {
+                    println!("{}: {}", path, error);
+                }
+                Metadata::default()
+            }
+        }
+    }
+
+    /// Every field but the description, as lines of "Label: value"
+    pub fn lines(&self) -> Vec<String> {
+        let fields = [
+            ("Released", &self.release),
+            ("Developer", &self.developer),
+            ("Publisher", &self.publisher),
+            ("Genre", &self.genre),
+            ("Players", &self.players),
+            ("Region", &self.region),
+            ("Rating", &self.rating),
+        ];
+
+        let mut lines = Vec::new();
+        for &(label, value) in fields.iter() {
+            if let Some(ref value) = *value {
+                lines.push(format!("{}: {}", label, value));
+            }
+        }
+        lines
+    }
+}
+
+/// One field of a sidecar as text, accepting numbers and dates written without quotes, and None if it is blank
+fn field(table: &toml::Table, key: &str, path: &str) -> Option<String> {
+    let value = match table.get(key) {
+        Some(&toml::Value::String(ref value)) => value.trim().to_string(),
+        Some(&toml::Value::Integer(value)) => value.to_string(),
+        Some(&toml::Value::Float(value)) => value.to_string(),
+        Some(&toml::Value::Datetime(ref value)) => value.clone(),
+        Some(_) => {
+            println!("{}: {} should be text", path, key);
+            return None;
+        },
+        None => return None
+    };
+
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
@@ -0,0 +1,69 @@
+//! Per-game changes to how a game is launched, from a `launch.toml` sidecar
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+
+use rustc_serialize::Decodable;
+use toml;
+
+/// Changes to the system's launch line for one game, all optional.
+///
+/// Every value may use the placeholders of `args`.
+#[derive(Clone, Debug, Default, RustcDecodable)]
+pub struct Overrides {
+    /// Emulator to run instead of the profile's
+    pub program: Option<String>,
+    /// Arguments to use instead of the profile's
+    pub args: Option<Vec<String>>,
+    /// Arguments added before the first one holding `%r`, or at the end if none does
+    pub extra_args: Option<Vec<String>>,
+    /// Working directory of the emulator
+    pub dir: Option<String>,
+    /// Environment variables to set, as an `[env]` table
+    pub env: Option<BTreeMap<String, String>>,
+}
+
+impl Overrides {
+    /// Read a sidecar, returning no overrides if there is none
+    pub fn load(path: &str) -> Overrides {
+        let mut string = String::new();
+        if path.is_empty() || File::open(path).and_then(|mut file| file.read_to_string(&mut string)).is_err() {
+            return Overrides::default();
+        }
+
+        let mut parser = toml::Parser::new(&string);
+        match parser.parse() {
+            Some(table) => match Overrides::decode(&mut toml::Decoder::new(toml::Value::Table(table))) {
+                Ok(overrides) => overrides,
+                Err(err) => {
+                    println!("{}: {}", path, err);
+                    Overrides::default()
+                }
+            },
+            None => {
+                for error in parser.errors.iter() {
+                    println!("{}: {}", path, error);
+                }
+                Overrides::default()
+            }
+        }
+    }
+
+    /// The arguments to launch with, given the profile's
+    pub fn args(&self, args: &[String]) -> Vec<String> {
+        let mut args = match self.args {
+            Some(ref args) => args.clone(),
+            None => args.to_vec()
+        };
+
+        if let Some(ref extra) = self.extra_args {
+            let i = args.iter().position(|x| x.contains("%r")).unwrap_or(args.len());
+            for (j, arg) in extra.iter().enumerate() {
+                args.insert(i + j, arg.clone());
+            }
+        }
+
+        args
+    }
+}
@@ -0,0 +1,239 @@
+//! Tags in No-Intro and GoodTools names, like `Sonic (Europe) (Rev 1) [h1]`
+
+use rom::RomFlags;
+
+/// Region names used in the tags of No-Intro and Redump names
+pub const REGIONS: &'static [&'static str] = &[
+    "World", "USA", "Europe", "Japan", "Asia", "Australia", "Brazil", "Canada", "China",
+    "France", "Germany", "Hong Kong", "Italy", "Korea", "Netherlands", "Russia", "Spain",
+    "Sweden", "Taiwan", "UK",
+];
+
+/// GoodTools region codes and their No-Intro names, like the `U` in `Super Mario World (U) [!]`
+const CODES: &'static [(&'static str, &'static str)] = &[
+    ("W", "World"), ("U", "USA"), ("E", "Europe"), ("J", "Japan"), ("A", "Australia"),
+    ("B", "Brazil"), ("C", "China"), ("F", "France"), ("G", "Germany"), ("H", "Netherlands"),
+    ("I", "Italy"), ("K", "Korea"), ("S", "Spain"), ("As", "Asia"), ("Ch", "China"),
+    ("Fc", "Canada"), ("HK", "Hong Kong"), ("Sw", "Sweden"), ("UK", "UK"),
+];
+
+/// What the tags of a name say about the dump
+#[derive(Clone, Debug, Default)]
+pub struct Tags {
+    /// The name without tags, with a trailing article moved to the front
+    pub title: String,
+    pub regions: Vec<String>,
+    /// Language codes, such as `En` and `Fr`
+    pub languages: Vec<String>,
+    /// Such as `1` for `(Rev 1)`
+    pub revision: Option<String>,
+    /// Such as `1.1` for `(v1.1)`
+    pub version: Option<String>,
+    /// Dump status from GoodTools brackets, such as `[!]` or `[h1]`
+    pub flags: Vec<RomFlags>,
+    /// Every other tag, such as `Beta` or `T+Eng`
+    pub other: Vec<String>,
+}
+
+/// Turn "Legend of Zelda, The - A Link to the Past" into "The Legend of Zelda - A Link to the Past"
+fn title(name: &str) -> String {
+    let (head, tail) = match name.find(" - ") {
+        Some(i) => (&name[.. i], &name[i ..]),
+        None => (name, "")
+    };
+
+    for article in ["The", "A", "An"].iter() {
+        let suffix = format!(", {}", article);
+        if head.ends_with(&suffix) {
+            return format!("{} {}{}", article, &head[.. head.len() - suffix.len()], tail);
+        }
+    }
+
+    name.to_string()
+}
+
+/// Regions of a GoodTools code, which can combine the main regions as in `(JUE)`
+fn codes(tag: &str) -> Option<Vec<&'static str>> {
+    if let Some(&(_, region)) = CODES.iter().find(|&&(code, _)| code == tag) {
+        return Some(vec![region]);
+    }
+
+    // Only these are combined, so that tags like `(GB)` are not taken for regions
+    if tag.len() < 2 || tag.len() > 4 || ! tag.chars().all(|c| "WUEJ".contains(c)) {
+        return None;
+    }
+    tag.chars().map(|c| {
+        CODES.iter().find(|&&(code, _)| code.len() == 1 && code.starts_with(c)).map(|&(_, region)| region)
+    }).collect()
+}
+
+/// True for No-Intro language codes such as `En` or `Pt-BR`
+fn is_language(code: &str) -> bool {
+    let mut parts = code.split('-');
+    let language: Vec<char> = parts.next().unwrap_or("").chars().collect();
+    if language.len() != 2 || ! language[0].is_ascii_uppercase() || ! language[1].is_ascii_lowercase() {
+        return false;
+    }
+    match parts.next() {
+        Some(country) => country.len() == 2 && country.chars().all(|c| c.is_ascii_uppercase()) && parts.next().is_none(),
+        None => true
+    }
+}
+
+impl Tags {
+    pub fn parse(name: &str) -> Tags {
+        let end = name.find(|c| c == '(' || c == '[').unwrap_or(name.len());
+        let mut tags = Tags {
+            title: title(name[.. end].trim()),
+            ..Tags::default()
+        };
+
+        let mut rest = &name[end ..];
+        while let Some(start) = rest.find(|c| c == '(' || c == '[') {
+            let close = if rest[start ..].starts_with('(') { ')' } else { ']' };
+            let tag_end = match rest[start + 1 ..].find(close) {
+                Some(i) => start + 1 + i,
+                None => break
+            };
+
+            let tag = rest[start + 1 .. tag_end].trim();
+            if close == ')' {
+                tags.paren(tag);
+            } else {
+                tags.bracket(tag);
+            }
+
+            rest = &rest[tag_end + 1 ..];
+        }
+
+        tags
+    }
+
+    fn region(&mut self, region: &str) {
+        if ! self.regions.iter().any(|x| x == region) {
+            self.regions.push(region.to_string());
+        }
+    }
+
+    fn flag(&mut self, flag: RomFlags) {
+        if ! self.flags.contains(&flag) {
+            self.flags.push(flag);
+        }
+    }
+
+    /// A tag in parentheses, used by No-Intro and by GoodTools for regions
+    fn paren(&mut self, tag: &str) {
+        let parts: Vec<&str> = tag.split(',').map(|x| x.trim()).collect();
+
+        if parts.iter().all(|x| REGIONS.contains(x)) {
+            for part in parts {
+                self.region(part);
+            }
+        } else if let Some(regions) = codes(tag) {
+            for region in regions {
+                self.region(region);
+            }
+        } else if parts.iter().all(|x| is_language(x)) {
+            self.languages.extend(parts.iter().map(|x| x.to_string()));
+        } else if tag.starts_with("Rev ") {
+            self.revision = Some(tag[4 ..].to_string());
+        } else if tag.starts_with('v') && tag[1 ..].starts_with(|c: char| c.is_ascii_digit()) {
+            self.version = Some(tag[1 ..].to_string());
+        } else if tag == "PD" {
+            self.flag(RomFlags::PublicDomain);
+        } else {
+            self.other.push(tag.to_string());
+        }
+    }
+
+    /// A GoodTools dump code in brackets, such as `[!]`, `[b1]` or `[h2C]`
+    fn bracket(&mut self, tag: &str) {
+        let flag = if tag == "!" {
+            Some(RomFlags::Good)
+        } else if tag.starts_with("cr") {
+            Some(RomFlags::Cracked)
+        } else {
+            match tag.chars().next() {
+                Some('a') => Some(RomFlags::Alternate),
+                Some('b') => Some(RomFlags::Bad),
+                Some('f') => Some(RomFlags::Fix),
+                Some('h') => Some(RomFlags::Hack),
+                Some('o') => Some(RomFlags::OverDump),
+                Some('t') => Some(RomFlags::Trainer),
+                _ => None
+            }
+        };
+
+        match flag {
+            Some(flag) => self.flag(flag),
+            None => self.other.push(tag.to_string())
+        }
+    }
+
+    /// Lines for the ROM view
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if ! self.regions.is_empty() {
+            lines.push(format!("Regions: {}", self.regions.join(", ")));
+        }
+        if ! self.languages.is_empty() {
+            lines.push(format!("Languages: {}", self.languages.join(", ")));
+        }
+        if let Some(ref revision) = self.revision {
+            lines.push(format!("Revision: {}", revision));
+        }
+        if let Some(ref version) = self.version {
+            lines.push(format!("Version: {}", version));
+        }
+        if ! self.other.is_empty() {
+            lines.push(format!("Tags: {}", self.other.join(", ")));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_intro() {
+        let tags = Tags::parse("Legend of Zelda, The - A Link to the Past (USA, Europe) (En,Fr,De) (Rev 1)");
+        assert_eq!(tags.title, "The Legend of Zelda - A Link to the Past");
+        assert_eq!(tags.regions, vec!["USA", "Europe"]);
+        assert_eq!(tags.languages, vec!["En", "Fr", "De"]);
+        assert_eq!(tags.revision, Some("1".to_string()));
+        assert!(tags.flags.is_empty());
+    }
+
+    #[test]
+    fn goodtools_regions() {
+        let tags = Tags::parse("Sonic the Hedgehog (JUE) [!]");
+        assert_eq!(tags.title, "Sonic the Hedgehog");
+        assert_eq!(tags.regions, vec!["Japan", "USA", "Europe"]);
+        assert_eq!(tags.flags, vec![RomFlags::Good]);
+
+        assert_eq!(Tags::parse("Pokemon Pinball (GB) (U)").other, vec!["GB"]);
+        assert_eq!(Tags::parse("Street Fighter II (HK)").regions, vec!["Hong Kong"]);
+    }
+
+    #[test]
+    fn goodtools_flags() {
+        let tags = Tags::parse("Super Mario World (U) (v1.1) [h1C] [b2] [cr Team] [t1] [T+Eng]");
+        assert_eq!(tags.version, Some("1.1".to_string()));
+        assert_eq!(tags.flags, vec![RomFlags::Hack, RomFlags::Bad, RomFlags::Cracked, RomFlags::Trainer]);
+        assert_eq!(tags.other, vec!["T+Eng"]);
+
+        let tags = Tags::parse("Tetris (PD) [a1] [x]");
+        assert_eq!(tags.flags, vec![RomFlags::PublicDomain, RomFlags::Alternate]);
+        assert_eq!(tags.other, vec!["x"]);
+    }
+
+    #[test]
+    fn unclosed() {
+        let tags = Tags::parse("Game (Beta) (USA");
+        assert_eq!(tags.title, "Game");
+        assert_eq!(tags.other, vec!["Beta"]);
+        assert!(tags.regions.is_empty());
+    }
+}
@@ -0,0 +1,210 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use library;
+use ls;
+
+/// Files that are never the game itself, even when a system accepts any extension
+const IGNORED: &'static [&'static str] = &[
+    "7z", "zip",
+    "jpg", "jpeg", "png",
+    "srm", "sav", "state", "cfg",
+    "txt", "nfo", "diz", "md", "pdf", "toml",
+];
+
+pub fn file_name(path: &str) -> String {
+    Path::new(path).file_name().and_then(|x| x.to_str()).unwrap_or("").to_string()
+}
+
+/// File name without its extension
+pub fn stem(path: &str) -> String {
+    Path::new(path).file_stem().and_then(|x| x.to_str()).unwrap_or("").to_string()
+}
+
+pub fn extension(path: &str) -> String {
+    Path::new(path).extension().and_then(|x| x.to_str()).unwrap_or("").to_lowercase()
+}
+
+fn hidden(path: &str) -> bool {
+    let name = file_name(path).to_lowercase();
+    name.starts_with('.') || name.starts_with("readme")
+}
+
+pub fn ignored(path: &str) -> bool {
+    let ext = extension(path);
+    hidden(path) || IGNORED.iter().any(|x| *x == ext)
+}
+
+/// True if a file could be the game, either by extension or, if none are configured, by not being ignored.
+///
+/// Listing an otherwise ignored extension such as zip makes it acceptable.
+pub fn matches(path: &str, extensions: &[String]) -> bool {
+    Path::new(path).is_file() && accepts(path, extensions)
+}
+
+/// The test of `matches` by name alone, for files that may be gone, like one just deleted
+pub fn accepts(path: &str, extensions: &[String]) -> bool {
+    if hidden(path) {
+        return false;
+    }
+
+    if extensions.is_empty() {
+        ! ignored(path)
+    } else {
+        let ext = extension(path);
+        extensions.iter().any(|x| x.trim_matches('.').to_lowercase() == ext)
+    }
+}
+
+/// Descriptor files that reference the rest of a multi-file game, in order of preference
+const DESCRIPTORS: &'static [&'static str] = &["m3u", "cue", "gdi"];
+
+/// True for cue, gdi and m3u files
+pub fn is_descriptor(path: &str) -> bool {
+    DESCRIPTORS.contains(&extension(path).as_str())
+}
+
+fn read_string(path: &str) -> String {
+    let mut string = String::new();
+    if let Ok(mut file) = File::open(path) {
+        let _ = file.read_to_string(&mut string);
+    }
+    string
+}
+
+/// Files referenced by a cue, gdi or m3u descriptor, relative to its directory
+pub fn tracks(path: &str) -> Vec<String> {
+    let mut tracks = Vec::new();
+
+    let ext = extension(path);
+    for line in read_string(path).lines() {
+        let line = line.trim();
+        let track = if ext == "cue" {
+            if line.to_uppercase().starts_with("FILE ") {
+                match (line.find('"'), line.rfind('"')) {
+                    (Some(start), Some(end)) if end > start => line[start + 1 .. end].to_string(),
+                    _ => line.split_whitespace().nth(1).unwrap_or("").to_string()
+                }
+            } else {
+                String::new()
+            }
+        } else if ext == "gdi" {
+            // Track lines are "number lba type sector_size file offset"
+            match (line.find('"'), line.rfind('"')) {
+                (Some(start), Some(end)) if end > start => line[start + 1 .. end].to_string(),
+                _ => line.split_whitespace().nth(4).unwrap_or("").to_string()
+            }
+        } else if ext == "m3u" {
+            if line.starts_with('#') {
+                String::new()
+            } else {
+                line.to_string()
+            }
+        } else {
+            String::new()
+        };
+
+        if ! track.is_empty() {
+            tracks.push(track);
+        }
+    }
+
+    tracks
+}
+
+/// Where the playlist of a multi-disc game is generated, so the ROM folder is never written to
+pub fn playlist_path(system: &str, name: &str) -> String {
+    // Not in the cache directory, where every folder is a system of the launch cache
+    let mut path = library::data_dir();
+    path.push("playlists");
+    path.push(system);
+    path.push(format!("{}.m3u", name));
+    path.to_string_lossy().into_owned()
+}
+
+/// Write an m3u playlist listing each disc by its full path, so the emulator can swap between them
+pub fn write_m3u(path: &str, discs: &[String]) -> Option<String> {
+    let mut m3u = String::new();
+    for disc in discs.iter() {
+        let disc = fs::canonicalize(disc).map(|x| x.to_string_lossy().into_owned()).unwrap_or(disc.clone());
+        m3u.push_str(&disc);
+        m3u.push('\n');
+    }
+
+    let written = match Path::new(path).parent() {
+        Some(parent) => fs::create_dir_all(parent),
+        None => Ok(())
+    }.and_then(|_| File::create(path)).and_then(|mut file| file.write_all(m3u.as_bytes()));
+
+    match written {
+        Ok(()) => Some(path.to_string()),
+        Err(err) => {
+            println!("{}: {}", path, err);
+            None
+        }
+    }
+}
+
+/// The file holding a game's data: the first track of a descriptor, following m3u to cue to bin
+pub fn data_file(path: &str) -> String {
+    let mut path = path.to_string();
+    // Bounded, as a playlist could list itself
+    for _ in 0 .. 4 {
+        if ! is_descriptor(&path) {
+            break;
+        }
+        let dir = Path::new(&path).parent().map(|x| x.to_path_buf()).unwrap_or_default();
+        match tracks(&path).into_iter().map(|track| dir.join(track)).find(|track| track.is_file()) {
+            Some(track) => path = track.to_string_lossy().into_owned(),
+            None => break
+        }
+    }
+    path
+}
+
+/// Every file in a directory that could be a game, leaving out tracks referenced by a descriptor
+pub fn find_roms(dir: &str, extensions: &[String]) -> Vec<String> {
+    let mut files: Vec<String> = ls(dir).into_iter().filter(|file| matches(file, extensions)).collect();
+
+    // Tracks belong to their descriptor and are never a game on their own
+    let mut referenced = Vec::new();
+    for file in files.iter() {
+        if is_descriptor(file) {
+            for track in tracks(file) {
+                referenced.push(file_name(&track).to_lowercase());
+            }
+        }
+    }
+    files.retain(|file| ! referenced.contains(&file_name(file).to_lowercase()));
+
+    files
+}
+
+/// Find the discs of a game directory, in order, the first being the file passed to the emulator.
+///
+/// Descriptor files (m3u, cue, gdi) are preferred over the tracks they reference, then
+/// extensions in the order they are listed. A game has several discs when more than one
+/// file of the preferred kind is found, such as one cue per disc.
+pub fn find_discs(dir: &str, extensions: &[String]) -> Vec<String> {
+    let files = find_roms(dir, extensions);
+
+    let mut order: Vec<String> = DESCRIPTORS.iter().map(|x| x.to_string()).collect();
+    for ext in extensions.iter() {
+        order.push(ext.trim_matches('.').to_lowercase());
+    }
+
+    for ext in order.iter() {
+        let discs: Vec<String> = files.iter().filter(|file| extension(file) == *ext).cloned().collect();
+        if ! discs.is_empty() {
+            return discs;
+        }
+    }
+
+    files
+}
+
+/// Find the file in a game directory that should be passed to the emulator
+pub fn find_rom(dir: &str, extensions: &[String]) -> Option<String> {
+    find_discs(dir, extensions).into_iter().next()
+}
@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Renderer;
+
+use cursor::Cursor;
+use emulator::Emulator;
+use font::Font;
+use texture::CenteredTexture;
+
+/// Rows of character keys on the on-screen keyboard
+const ROWS: [&'static str; 4] = ["1234567890", "QWERTYUIOP", "ASDFGHJKL", "ZXCVBNM"];
+
+/// Height of one key row
+const KEY_HEIGHT: i32 = 48;
+
+/// How well `query` matches `text`, or None if it does not.
+///
+/// Every character of the query, ignoring case and spaces, has to appear in order in the text.
+/// Runs of consecutive characters and characters starting a word score higher.
+pub fn fuzzy(query: &str, text: &str) -> Option<usize> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let mut score = 0;
+    let mut start = 0;
+    let mut last = None;
+    for c in query.to_lowercase().chars().filter(|c| ! c.is_whitespace()) {
+        let found = start + text[start ..].iter().position(|&x| x == c)?;
+
+        score += 1;
+        if last.map(|last| last + 1 == found).unwrap_or(false) {
+            score += 4;
+        }
+        if found == 0 || ! text[found - 1].is_alphanumeric() {
+            score += 2;
+        }
+
+        last = Some(found);
+        start = found + 1;
+    }
+
+    Some(score)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Key {
+    Char(char),
+    Space,
+    Delete,
+    Clear,
+    Done,
+}
+
+impl Key {
+    fn label(&self) -> String {
+        match *self {
+            Key::Char(c) => c.to_string(),
+            Key::Space => "Space".to_string(),
+            Key::Delete => "Delete".to_string(),
+            Key::Clear => "Clear".to_string(),
+            Key::Done => "Done".to_string(),
+        }
+    }
+}
+
+/// Keys of the on-screen keyboard as (key, x, y, w, h), along the bottom of the window
+fn keys(width: i32, height: i32) -> Vec<(Key, i32, i32, i32, i32)> {
+    let w = width / 10;
+    let mut y = height - KEY_HEIGHT * (ROWS.len() as i32 + 1);
+
+    let mut keys = Vec::new();
+    for row in ROWS.iter() {
+        let mut x = (width - w * row.len() as i32) / 2;
+        for c in row.chars() {
+            keys.push((Key::Char(c), x, y, w, KEY_HEIGHT));
+            x += w;
+        }
+        y += KEY_HEIGHT;
+    }
+
+    let mut x = 0;
+    for &(ref key, span) in [(Key::Space, 4), (Key::Delete, 2), (Key::Clear, 2), (Key::Done, 2)].iter() {
+        keys.push((key.clone(), x, y, w * span, KEY_HEIGHT));
+        x += w * span;
+    }
+
+    keys
+}
+
+/// Type-to-search, with an on-screen keyboard for controllers
+pub struct Search {
+    pub query: String,
+    /// The on-screen keyboard is shown
+    pub keyboard: bool,
+}
+
+impl Search {
+    pub fn new() -> Search {
+        Search {
+            query: String::new(),
+            keyboard: false,
+        }
+    }
+
+    /// True while there is a query or the keyboard is open
+    pub fn active(&self) -> bool {
+        ! self.query.is_empty() || self.keyboard
+    }
+
+    pub fn push(&mut self, text: &str) {
+        self.query.push_str(text);
+    }
+
+    pub fn pop(&mut self) {
+        self.query.pop();
+    }
+
+    pub fn clear(&mut self) {
+        self.query.clear();
+        self.keyboard = false;
+    }
+
+    /// True if the title matches the query, which every title does while it is empty
+    pub fn matches(&self, title: &str) -> bool {
+        fuzzy(&self.query, title).is_some()
+    }
+
+    /// ROMs of every system matching the query, as (system, index into the system's ROMs), best first
+    pub fn results(&self, emulators: &BTreeMap<String, Emulator>) -> Vec<(String, usize)> {
+        let mut results = Vec::new();
+        for (key, emulator) in emulators.iter() {
+            for (index, rom) in emulator.roms.iter().enumerate() {
+                if let Some(score) = fuzzy(&self.query, rom.config.title()) {
+                    results.push((score, rom.config.title(), key, index));
+                }
+            }
+        }
+        results.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+
+        results.into_iter().map(|(_, _, key, index)| (key.clone(), index)).collect()
+    }
+
+    /// Press the key under the cursor, returning true if the click landed on the keyboard
+    pub fn click(&mut self, cursor: &Cursor, width: i32, height: i32) -> bool {
+        if ! self.keyboard {
+            return false;
+        }
+
+        for (key, x, y, w, h) in keys(width, height) {
+            if cursor.inside(x, y, w, h) {
+                match key {
+                    Key::Char(c) => self.query.push(c.to_ascii_lowercase()),
+                    Key::Space => self.query.push(' '),
+                    Key::Delete => self.pop(),
+                    Key::Clear => self.query.clear(),
+                    Key::Done => self.keyboard = false,
+                }
+                return true;
+            }
+        }
+
+        let top = height - KEY_HEIGHT * (ROWS.len() as i32 + 1);
+        cursor.inside(0, top, width, height - top)
+    }
+
+    /// Draw the on-screen keyboard, if it is open, over the current view
+    pub fn draw(&self, renderer: &mut Renderer, font: &Font, cursor: &Cursor, width: i32, height: i32) {
+        if ! self.keyboard {
+            return;
+        }
+
+        let top = height - KEY_HEIGHT * (ROWS.len() as i32 + 1);
+        renderer.set_draw_color(Color::RGB(255, 255, 255));
+        renderer.fill_rect(Rect::new(0, top, width as u32, (height - top) as u32).unwrap().unwrap());
+
+        for (key, x, y, w, h) in keys(width, height) {
+            if cursor.inside(x, y, w, h) {
+                renderer.set_draw_color(Color::RGB(224, 224, 224));
+                renderer.fill_rect(Rect::new(x, y, w as u32, h as u32).unwrap().unwrap());
+            }
+            renderer.set_draw_color(Color::RGB(192, 192, 192));
+            renderer.draw_rect(Rect::new(x, y, w as u32, h as u32).unwrap().unwrap());
+
+            let texture = CenteredTexture::new(font.render(&renderer, &key.label(), Color::RGB(0, 0, 0)));
+            texture.draw(renderer, x, y, w, h);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use collection::Rules;
+
+/// Frontend-wide settings, read from the `[emulition]` table of config.toml
+#[derive(Default, RustcDecodable)]
+pub struct Settings {
+    pub dats: Option<String>,
+    /// Size limit of the launch cache for compressed ROMs, in megabytes
+    pub cache_size: Option<u64>,
+    /// Directory that new ROMs are dropped into, to be filed under their system
+    pub inbox: Option<String>,
+    /// Link inbox files into the library instead of moving them
+    pub inbox_symlink: Option<bool>,
+    /// Names of collections that games are added to from the ROM view
+    pub collections: Option<Vec<String>>,
+    /// Smart collections, as `[[emulition.smart]]` tables of rules
+    pub smart: Option<Vec<Rules>>,
+    /// Regions to prefer when picking one version of a game, best first
+    pub regions: Option<Vec<String>>,
+    /// Language codes to prefer when picking one version of a game, best first
+    pub languages: Option<Vec<String>>,
+    /// Commands run before every launch, each a program and its arguments
+    pub pre_launch: Option<Vec<Vec<String>>>,
+    /// Commands run after every emulator exits
+    pub post_exit: Option<Vec<Vec<String>>>,
+    /// Seconds a hook may run before it is killed
+    pub hook_timeout: Option<u64>,
+}
+
+impl Settings {
+    /// Directory holding No-Intro and Redump DAT files
+    pub fn dats(&self) -> &str {
+        match self.dats {
+            Some(ref dats) => dats,
+            None => "dats"
+        }
+    }
+
+    /// Size limit of the launch cache in bytes
+    pub fn cache_size(&self) -> u64 {
+        self.cache_size.unwrap_or(4096) * 1024 * 1024
+    }
+
+    pub fn inbox_symlink(&self) -> bool {
+        self.inbox_symlink.unwrap_or(false)
+    }
+
+    pub fn collections(&self) -> &[String] {
+        match self.collections {
+            Some(ref collections) => collections,
+            None => &[]
+        }
+    }
+
+    pub fn smart(&self) -> &[Rules] {
+        match self.smart {
+            Some(ref smart) => smart,
+            None => &[]
+        }
+    }
+
+    pub fn regions(&self) -> Vec<String> {
+        match self.regions {
+            Some(ref regions) => regions.clone(),
+            None => vec!["USA".to_string(), "World".to_string(), "Europe".to_string(), "Japan".to_string()]
+        }
+    }
+
+    pub fn languages(&self) -> Vec<String> {
+        match self.languages {
+            Some(ref languages) => languages.clone(),
+            None => vec!["En".to_string()]
+        }
+    }
+
+    pub fn pre_launch(&self) -> &[Vec<String>] {
+        match self.pre_launch {
+            Some(ref hooks) => hooks,
+            None => &[]
+        }
+    }
+
+    pub fn post_exit(&self) -> &[Vec<String>] {
+        match self.post_exit {
+            Some(ref hooks) => hooks,
+            None => &[]
+        }
+    }
+
+    pub fn hook_timeout(&self) -> u64 {
+        self.hook_timeout.unwrap_or(60)
+    }
+}
@@ -0,0 +1,151 @@
+//! Placeholders in the `program` and `args` of a system
+
+use std::env;
+use std::path::Path;
+
+use scan;
+
+/// What the placeholders of a launch line stand for
+pub struct Vars {
+    /// `%r`, the ROM file to run
+    pub rom: String,
+    /// `%n`, the name of the game
+    pub name: String,
+    /// `%d`, the directory holding the ROM file
+    pub dir: String,
+    /// `%b`, the ROM file name without its extension
+    pub base: String,
+    /// `%s`, the key of the system in config.toml
+    pub system: String,
+    /// `%e`, the extension of the ROM file, in lowercase
+    pub ext: String,
+    /// `%h`, the home directory
+    pub home: String,
+}
+
+impl Vars {
+    pub fn new(system: &str, name: &str, file: &str) -> Vars {
+        Vars {
+            rom: file.to_string(),
+            name: name.to_string(),
+            dir: Path::new(file).parent().and_then(|x| x.to_str()).unwrap_or("").to_string(),
+            base: scan::stem(file),
+            system: system.to_string(),
+            ext: scan::extension(file),
+            home: env::var("HOME").unwrap_or(String::new()),
+        }
+    }
+
+    fn get(&self, c: char) -> Option<&str> {
+        match c {
+            'r' => Some(&self.rom),
+            'n' => Some(&self.name),
+            'd' => Some(&self.dir),
+            'b' => Some(&self.base),
+            's' => Some(&self.system),
+            'e' => Some(&self.ext),
+            'h' => Some(&self.home),
+            _ => None
+        }
+    }
+
+    /// Replace placeholders like `%r` and environment variables like `${HOME}`.
+    ///
+    /// `%%` is a percent sign, and unknown placeholders are left alone. Unset variables are empty.
+    pub fn expand(&self, text: &str) -> String {
+        let mut expanded = String::new();
+
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == '%' {
+                let next = chars.peek().cloned();
+                if next == Some('%') {
+                    chars.next();
+                    expanded.push('%');
+                } else if let Some(value) = next.and_then(|x| self.get(x)) {
+                    chars.next();
+                    expanded.push_str(value);
+                } else {
+                    expanded.push('%');
+                }
+            } else if c == '$' && chars.peek() == Some(&'{') {
+                chars.next();
+                let var: String = chars.by_ref().take_while(|&x| x != '}').collect();
+                expanded.push_str(&env::var(&var).unwrap_or(String::new()));
+            } else {
+                expanded.push(c);
+            }
+        }
+
+        expanded
+    }
+
+    /// True if `A=B` or `A!=B` holds, or for a bare value, if it is not empty
+    fn condition(&self, condition: &str) -> bool {
+        if let Some(i) = condition.find("!=") {
+            self.expand(&condition[.. i]) != self.expand(&condition[i + 2 ..])
+        } else if let Some(i) = condition.find('=') {
+            self.expand(&condition[.. i]) == self.expand(&condition[i + 1 ..])
+        } else {
+            ! self.expand(condition).is_empty()
+        }
+    }
+
+    /// Expand one argument, or return None to leave it out.
+    ///
+    /// Arguments written as `?CONDITION:ARG` are only passed when the condition holds,
+    /// as in `?%e=elf:--elf=%r` or `?${CONFIG_DIR}:--config=${CONFIG_DIR}`.
+    pub fn arg(&self, arg: &str) -> Option<String> {
+        if arg.starts_with('?') {
+            if let Some(i) = arg.find(':') {
+                if ! self.condition(&arg[1 .. i]) {
+                    return None;
+                }
+                return Some(self.expand(&arg[i + 1 ..]));
+            }
+        }
+
+        Some(self.expand(arg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> Vars {
+        Vars::new("ps2", "Okami", "/roms/ps2/Okami/Okami (USA).iso")
+    }
+
+    #[test]
+    fn placeholders() {
+        let vars = vars();
+        assert_eq!(vars.expand("%r"), "/roms/ps2/Okami/Okami (USA).iso");
+        assert_eq!(vars.expand("%d/%b.%e"), "/roms/ps2/Okami/Okami (USA).iso");
+        assert_eq!(vars.expand("%n on %s"), "Okami on ps2");
+        assert_eq!(vars.expand("100%% %x %"), "100% %x %");
+    }
+
+    #[test]
+    fn environment() {
+        env::set_var("EMULITION_TEST_EXPAND", "/etc/pcsx2");
+        assert_eq!(vars().expand("--cfg=${EMULITION_TEST_EXPAND}/%s"), "--cfg=/etc/pcsx2/ps2");
+        assert_eq!(vars().expand("${EMULITION_TEST_UNSET}x"), "x");
+    }
+
+    #[test]
+    fn conditions() {
+        let vars = vars();
+        assert_eq!(vars.arg("?%e=iso:--iso=%r"), Some("--iso=/roms/ps2/Okami/Okami (USA).iso".to_string()));
+        assert_eq!(vars.arg("?%e=elf:--elf=%r"), None);
+        assert_eq!(vars.arg("?%e!=elf:--fullscreen"), Some("--fullscreen".to_string()));
+        assert_eq!(vars.arg("?%e!=iso:--fullscreen"), None);
+
+        env::set_var("EMULITION_TEST_CONDITION", "/tmp");
+        assert_eq!(vars.arg("?${EMULITION_TEST_CONDITION}:--config=${EMULITION_TEST_CONDITION}"), Some("--config=/tmp".to_string()));
+        assert_eq!(vars.arg("?${EMULITION_TEST_CONDITION_UNSET}:--config"), None);
+
+        // Without a colon, it is a plain argument
+        assert_eq!(vars.arg("?%e"), Some("?iso".to_string()));
+    }
+}
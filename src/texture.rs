@@ -16,6 +16,10 @@ impl NormalTexture {
         let query = self.texture.query();
         renderer.copy(&self.texture, None, Rect::new(x, y, query.width, query.height).unwrap());
     }
+
+    pub fn height(&self) -> i32 {
+        self.texture.query().height as i32
+    }
 }
 
 pub struct CenteredTexture {